@@ -3,8 +3,8 @@
 // The fee for each transaction on the OptiFi system, currently set at 0.05%
 pub const FEE: f32 = 0.0005;
 
-// The fee (in USD) that a cranker will receive.
-pub const CRANKER_FEE: f32 = 0.002;
+// The fee (in usdc, with USDC_DECIMALS) that a cranker will receive.
+pub const CRANKER_FEE: u64 = 2_000;
 pub const MM_BALANCE_THRESHOLD: f32 = 0.1;
 
 // Current version of the market schema
@@ -13,6 +13,31 @@ pub const MARKET_VERSION: i32 = 1;
 // Orderbook spread limit for penalties, 1%
 pub const SPREAD_LIMIT: f32 = 0.01;
 
+// Default max distance of a market order's limit price from the theoretical option price,
+// in bps, the exchange authority can change it with set_market_order_price_band
+pub const DEFAULT_MARKET_ORDER_PRICE_BAND_BPS: u64 = 2000;
+// Buffer on the pc locked by a market or reduce-only bid for the serum taker fee, in bps
+pub const MARKET_ORDER_FEE_BUFFER_BPS: u64 = 100;
+
+// Volume based trading fee tiers, (traded volume in usdc, maker fee bps, taker fee bps),
+// the user pays the fees of the highest tier its traded volume reaches
+pub const FEE_TIERS: [(u64, u64, u64); 4] = [
+    (0, 2, 5),
+    (1_000_000, 1, 4),
+    (10_000_000, 0, 3),
+    (50_000_000, 0, 2),
+];
+
+// Max number of open orders owners linked to a user for self trade prevention
+pub const MAX_LINKED_OWNERS: usize = 4;
+
+// Max number of user accounts which link the amm as the same owner for self trade prevention,
+// they're all passed to the amm order updates
+pub const MAX_AMM_LINKED_USERS: usize = 16;
+
+// Max number of legs of a combo order
+pub const MAX_COMBO_LEGS: usize = 4;
+
 /// How many strikes to generate on either side of a spot,
 /// and the increment in USD they'll be generated at.
 /// This value MUST be odd for the strike ladder to be generated
@@ -60,8 +85,17 @@ pub const SWITCHBOARD_MAINNET_ETH_IV: &str = "4AGPMUEfBCSNqVd4Y6veHAep6VPtrkMa89
 // #[cfg(feature = "devnet")]
 // const ETH_IV_ORACLE: &str = SWITCHBOARD_DEVNET_ETH_IV;
 
+// Max number of tenors in the iv term structure of an asset, e.g. 7d, 30d, 90d
+pub const MAX_IV_TERM_ORACLES: usize = 6;
+
+// Max number of stress scenarios of an asset in the risk parameter account
+pub const MAX_STRESS_SCENARIOS: usize = 40;
+
 pub const USDC_DECIMALS: u32 = 6u32;
 
+// Max deviation of usdc/usd from 1 before margin stress and new listings are halted, 2%
+pub const USDC_PEG_TOLERANCE: f32 = 0.02;
+
 // Constant for the AMM
 pub const DELTA_LIMIT: f32 = 0.05; // delta limit for hedging
 pub const TRADE_CAPACITY: f32 = 0.25;
@@ -82,8 +116,44 @@ pub const SECS_IN_DAY: u64 = SECS_IN_HOUR * HOURS_IN_DAY;
 pub const SECS_IN_STANDARD_YEAR: u64 = SECS_IN_DAY * DAYS_IN_STANDARD_YEAR;
 
 // Constant for the margin calculation
+// the default stress grid of a new risk parameter account
 pub const STRESS: f32 = 0.3;
 pub const STEP: u8 = 5;
-
-pub const LIQUIDATION: f32 = 0.9;
+// relative iv shock of the stress scenarios, and the number of steps on either side
+pub const VOL_STRESS: f32 = 0.3;
+pub const VOL_STEP: u8 = 1;
+// floor of the shocked iv in stress scenarios
+pub const MIN_STRESS_IV: f32 = 0.05;
+// compute units of one margin_stress_calculate, the max of a transaction
+pub const MARGIN_STRESS_COMPUTE_BUDGET: u64 = 200_000;
+// compute units of margin_stress_calculate besides the pricing,
+// mostly the (de)serialization of the margin stress account
+pub const MARGIN_STRESS_FIXED_UNITS: u64 = 50_000;
+// compute units of pricing a row of the stress grid, the scenarios of the same vol shock,
+// for 1 strike: 500 for the row and 23200 for each spot of the row
+// (d1 2859, d2 5000, 2 cdfs 7000 each), so 23700 for the current price
+pub const MARGIN_STRESS_ROW_UNITS: u64 = 500;
+pub const MARGIN_STRESS_SPOT_UNITS: u64 = 23_200;
+// compute units of the intrinsic value of 1 strike
+pub const MARGIN_STRESS_INTRINSIC_UNITS: u64 = 1_300;
+// margin_stress_sync keeps the calculated stress results available, without repricing,
+// while spot and iv move less than these relative amounts since the last calculation
+pub const SMALL_SPOT_MOVE: f32 = 0.005;
+pub const SMALL_IV_MOVE: f32 = 0.02;
+// max age in seconds of the cached stress results
+pub const MARGIN_STRESS_CACHE_AGE: u64 = 3600;
+// max age in seconds of the margin stress data, older data can't be used for margin
+pub const MAX_MARGIN_STRESS_AGE: u64 = 600;
+// max age in seconds of the oracle data the margin stress is synced from,
+// and the instruments are created and settled at
+pub const MAX_ORACLE_AGE: u64 = 600;
+// instruments a margin stress account is sized for on top of the currently listed ones,
+// so that new listings can be resynced without re-creating the account
+pub const MARGIN_STRESS_SPARE_INSTRUMENTS: usize = 8;
+// max space of an account created by the program
+pub const MAX_ACCOUNT_SPACE: usize = 10240;
+
+// initial margin = maintenance margin * INITIAL_MARGIN_MULTIPLIER,
+// the buffer between the two keeps a new position from being liquidated right away
+pub const INITIAL_MARGIN_MULTIPLIER: f32 = 1.25;
 pub const LIQUIDATION_SLIPPAGE: f32 = 1.0;
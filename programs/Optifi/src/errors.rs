@@ -88,4 +88,79 @@ pub enum ErrorCode {
 
     #[msg("Should update the margin stress again")]
     TimeOut,
+
+    #[msg("Mock oracle is only available with the testing feature")]
+    MockOracleDisabled,
+
+    #[msg("IV term structure tenors must be positive and sorted in ascending order")]
+    InvalidIvTermStructure,
+
+    #[msg("USDC is out of the peg tolerance")]
+    UsdcDepegged,
+
+    #[msg("User margin is above the maintenance margin")]
+    UserNotLiquidatable,
+
+    #[msg("Correlation must be between -1 and 1, between two different assets")]
+    InvalidCorrelation,
+
+    #[msg("Stress scenarios must be non empty and can't shock the spot or iv to zero")]
+    InvalidStressScenarios,
+
+    #[msg("Margin stress account is out of sync with the exchange instruments, resync it")]
+    MarginStressOutOfSync,
+
+    #[msg("Margin stress account is too small for the instruments")]
+    MarginStressAccountTooSmall,
+
+    #[msg("Fill-or-kill order can't be filled in full")]
+    OrderNotFilled,
+
+    #[msg("Market order limit price is outside the price band around the theoretical price")]
+    MarketOrderOutOfBand,
+
+    #[msg("Combo order must have 1 to MAX_COMBO_LEGS legs in different instruments, with their accounts")]
+    InvalidComboOrder,
+
+    #[msg("Order type isn't supported by the instruction")]
+    InvalidOrderType,
+
+    #[msg("Reduce-only order can't reduce the user's position")]
+    ReduceOnlyOrderRejected,
+
+    #[msg("Invalid order expiry")]
+    InvalidOrderExpiry,
+
+    #[msg("Order is not expired")]
+    OrderNotExpired,
+
+    #[msg("Invalid referrer")]
+    InvalidReferrer,
+
+    #[msg("Referral fee share must be at most 10000 bps")]
+    InvalidReferralFeeShare,
+
+    #[msg("Order would trade against a resting order of the same owner")]
+    SelfTradeNotAllowed,
+
+    #[msg("Post-only order would have crossed the orderbook")]
+    PostOnlyOrderNotPosted,
+
+    #[msg("Market order price band must be below 10000 bps")]
+    InvalidMarketOrderPriceBand,
+
+    #[msg("Margin stress account is already in sync with the exchange instruments")]
+    MarginStressInSync,
+
+    #[msg("Oracle data is too old")]
+    StaleOracle,
+
+    #[msg("Too many users link the amm for self trade prevention")]
+    AmmLinkedUsersFull,
+
+    #[msg("A user account linking the amm wasn't passed")]
+    AmmLinkedUserMissing,
+
+    #[msg("An order with expiry can only be replaced with the same client order id")]
+    OrderExpiryNotCarriedOver,
 }
@@ -1,12 +1,12 @@
 use std::borrow::Borrow;
 
 use crate::{
-    constants::{SECS_IN_STANDARD_YEAR, STEP, STRESS},
-    state::{InstrumentCommon, InstrumentUnique, UserPosition},
+    constants::{MIN_STRESS_IV, SECS_IN_STANDARD_YEAR, STEP, STRESS, VOL_STEP, VOL_STRESS},
+    state::{InstrumentCommon, InstrumentUnique, MarginStressAccount, UserAccount, UserPosition},
     u_to_f_repr,
 };
 use anchor_lang::prelude::*;
-use ndarray::{Array, Array2};
+use ndarray::Array;
 use solana_program::log::sol_log_compute_units;
 
 use super::{option_intrinsic_value, option_price, Asset, SpotInputOption};
@@ -45,25 +45,70 @@ pub struct StressFunctionResult {
     pub stress_price_delta: Vec<Vec<f32>>,
 }
 
-/// margin function result
-#[derive(AnchorSerialize, AnchorDeserialize, Debug)]
+/// margin function result, all the amounts are in usdc (6 decimals repr)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
 pub struct MarginFunctionResult {
-    net_qty: i64,
-    notional_qty: i64,
-    net: f32,
-    notional: f32,
-    stress_result: f32,
-    net_intrinsic: f32,
-    net_premium: f32,
-    maturing_net_intrinsic: f32,
-    maturing_premium: f32,
-    maturing_liquidity: f32,
-    pub total_margin: f32,
+    /// net contract position
+    pub net_qty: i64,
+    /// notional contract position
+    pub notional_qty: i64,
+    /// net position in usdc
+    pub net: i64,
+    /// notional position in usdc
+    pub notional: i64,
+    /// worst portfolio value change across the stress grid
+    pub stress_result: i64,
+    pub net_intrinsic: i64,
+    pub net_premium: i64,
+    /// net intrinsic value of the soonest maturing options
+    pub maturing_net_intrinsic: i64,
+    /// premium add on for the soonest maturing options
+    pub maturing_premium: i64,
+    /// liquidity add on for the soonest maturing options
+    pub maturing_liquidity: i64,
+    /// margin requirement, negative means margin should be reserved
+    pub total_margin: i64,
     pub net_leverage: f32,
-    notional_leverage: f32,
+    pub notional_leverage: f32,
+}
+
+/// calculates the stress scenario grid of spot shocks x vol shocks,
+/// returns the relative spot shock and relative vol shock of each scenario
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::generate_stress_scenarios;
+///
+/// let (spot_shocks, vol_shocks) = generate_stress_scenarios(0.3, 1, 0.5, 1);
+///
+/// assert_eq!(spot_shocks, vec![-0.3, 0.0, 0.3, -0.3, 0.0, 0.3, -0.3, 0.0, 0.3]);
+/// assert_eq!(vol_shocks, vec![-0.5, -0.5, -0.5, 0.0, 0.0, 0.0, 0.5, 0.5, 0.5]);
+/// ```
+pub fn generate_stress_scenarios(
+    stress: f32,
+    step: u8,
+    vol_stress: f32,
+    vol_step: u8,
+) -> (Vec<f32>, Vec<f32>) {
+    let mut spot_shocks = vec![];
+    let mut vol_shocks = vec![];
+    for j in 0..(vol_step * 2 + 1) {
+        let vol_shock = if vol_step == 0 {
+            0.0
+        } else {
+            vol_stress / vol_step as f32 * j as f32 - vol_stress
+        };
+        for i in 0..(step * 2 + 1) {
+            spot_shocks.push(stress / step as f32 * i as f32 - stress);
+            vol_shocks.push(vol_shock);
+        }
+    }
+    (spot_shocks, vol_shocks)
 }
 
 /// stress_function
+/// reprices the options at every scenario of the stress grid, and returns the
+/// option price changes against the current price for each scenario
 pub fn stress_function(
     spot: f32,
     strike: Vec<f32>,
@@ -71,67 +116,31 @@ pub fn stress_function(
     r: f32,
     q: f32,
     t: &Vec<f32>,
-    stress: f32,
     is_call: Vec<u8>,
-    step: u8,
+    spot_shocks: &Vec<f32>,
+    vol_shocks: &Vec<f32>,
 ) -> StressFunctionResult {
     // main values: prices, reg-t margins, delta, intrinsic values
     // 23700 computing units for 1 strikes
     let spots = SpotInputOption::SingleSpot(spot);
     let price = option_price(spots.borrow(), strike.borrow(), iv, r, q, &t, &is_call);
-    // let reg_t_margin = option_reg_t_margin(spots.borrow(), &strike, stress, &is_call);
-    // let delta = option_delta(&spots, &strike, iv, r, q, &t, &is_call);
 
     // 1300 computing units for 1 strikes
     let intrinsic = option_intrinsic_value(&spots, &strike, &is_call);
 
-    // sol_log_compute_units();
-    // old version
-    // let stress_spot = generate_stress_spot(spot, stress, 1);
-    // let spots = SpotInputOption::MultiSpots(stress_spot);
-    // let stress_price = option_price(&spots, &strike, iv, r, q, &t, &is_call);
-
-    // let mut result: Vec<Vec<f32>> = vec![];
-    // for (i, stress_p_vec) in stress_price.iter().enumerate() {
-    //     let mut temp = vec![];
-    //     for stress_p in stress_p_vec {
-    //         temp.push(stress_p - price[i][0]);
-    //     }
-    //     result.push(temp);
-    // }
-
-    // 47400 computing units
-    // new version
-    let stress_spot_down = spot * (1.0 - stress);
-    let stress_spot_up = spot * (1.0 + stress);
-
-    let mut stress_price = option_price(
-        &SpotInputOption::MultiSpots(vec![vec![stress_spot_down, stress_spot_up]]),
+    let stress_price = stress_price_delta(
+        spot,
         &strike,
         iv,
         r,
         q,
-        &t,
+        t,
         &is_call,
+        &price,
+        spot_shocks,
+        vol_shocks,
     );
 
-    // 2600 computing units
-    for (i, option_prices_in_stress_prices) in stress_price.iter_mut().enumerate() {
-        let down = option_prices_in_stress_prices[0] - price[i][0];
-        let up = option_prices_in_stress_prices[1] - price[i][0];
-
-        let new_len = step * 2;
-
-        let range = (up - down) / new_len as f32;
-
-        let last_index = new_len - 1;
-        option_prices_in_stress_prices.resize(new_len as usize, 0.0);
-
-        for i in 0..step {
-            option_prices_in_stress_prices[i as usize] = down + range * i as f32;
-            option_prices_in_stress_prices[(last_index - i) as usize] = up - range * i as f32;
-        }
-    }
     return StressFunctionResult {
         price,
         // reg_t_margin,
@@ -141,14 +150,92 @@ pub fn stress_function(
     };
 }
 
+/// reprices the options at the given scenarios of the stress grid, and returns the
+/// option price changes against `price` for each scenario,
+/// each scenario costs about as much as one pricing of the options
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::{option_price, stress_price_delta, SpotInputOption};
+///
+/// let (strike, t, is_call) = (vec![100.0], vec![0.25], vec![1]);
+/// let price = option_price(&SpotInputOption::SingleSpot(100.0), &strike, 0.5, 0.0, 0.0, &t, &is_call);
+/// let shocks = [-0.1, 0.0, 0.1];
+/// let delta = stress_price_delta(100.0, &strike, 0.5, 0.0, 0.0, &t, &is_call, &price, &shocks, &[0.0; 3]);
+/// assert_eq!(delta[0].len(), 3);
+/// assert!(delta[0][0] < 0.0 && delta[0][1].abs() < 1e-3 && delta[0][2] > 0.0);
+/// ```
+pub fn stress_price_delta(
+    spot: f32,
+    strike: &Vec<f32>,
+    iv: f32,
+    r: f32,
+    q: f32,
+    t: &Vec<f32>,
+    is_call: &Vec<u8>,
+    price: &Vec<Vec<f32>>,
+    spot_shocks: &[f32],
+    vol_shocks: &[f32],
+) -> Vec<Vec<f32>> {
+    let mut stress_price: Vec<Vec<f32>> = vec![vec![]; strike.len()];
+
+    // full reprice at every node of the grid,
+    // the scenarios with the same vol shock are priced together
+    let mut start = 0;
+    while start < spot_shocks.len() {
+        let vol_shock = vol_shocks[start];
+        let end = start
+            + vol_shocks[start..]
+                .iter()
+                .take_while(|&&v| v == vol_shock)
+                .count();
+
+        let stress_spots = spot_shocks[start..end]
+            .iter()
+            .map(|shock| spot * (1.0 + shock))
+            .collect::<Vec<f32>>();
+        let stress_iv = (iv * (1.0 + vol_shock)).max(MIN_STRESS_IV);
+
+        let prices = option_price(
+            &SpotInputOption::MultiSpots(vec![stress_spots]),
+            strike,
+            stress_iv,
+            r,
+            q,
+            t,
+            is_call,
+        );
+
+        for (i, option_prices_in_stress_prices) in prices.iter().enumerate() {
+            for p in option_prices_in_stress_prices {
+                stress_price[i].push(p - price[i][0]);
+            }
+        }
+
+        start = end;
+    }
+
+    stress_price
+}
+
 /// Margin function
 pub fn margin_function(
     user: Vec<i64>,
+    spot: u64,
     t: &Vec<f32>,
     price: &Vec<u64>,
     intrinsic: &Vec<u64>,
     stress_price_change: &Vec<Vec<i64>>,
-) -> i64 {
+) -> MarginFunctionResult {
+    // # net contract position
+    let net_qty: i64 = user.iter().sum();
+    // # notional contract position
+    let notional_qty: i64 = user.iter().map(|q| q.abs()).sum();
+    // # net and notional position in usdc
+    // in i128, a large position times the spot price can overflow i64
+    let net = (net_qty as i128 * spot as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+    let notional = (notional_qty as i128 * spot as i128).min(i64::MAX as i128) as i64;
+
     let user_matrix = Array::from_vec(user.clone());
 
     let shape = (stress_price_change.len(), stress_price_change[0].len());
@@ -157,6 +244,7 @@ pub fn margin_function(
     let stress_price_change_matrix = Array::from_shape_vec(shape, stress_price_change_vec).unwrap();
 
     let new_matrix = user_matrix.dot(&stress_price_change_matrix);
+    // worst portfolio value change across all the scenarios of the stress grid
     let stress_result = new_matrix.iter().copied().fold(i64::MAX, i64::min);
 
     let net_intrinsic = intrinsic
@@ -182,6 +270,12 @@ pub fn margin_function(
         }
     }
 
+    let maturing_net_intrinsic = intrinsic
+        .iter()
+        .enumerate()
+        .map(|(index, &v)| v as i64 * user[index] * min_t[index])
+        .sum::<i64>();
+
     let matrix1 = t
         .iter()
         .enumerate()
@@ -219,9 +313,243 @@ pub fn margin_function(
         0
     };
 
+    // # total margin
     let total_margin = margin_1 + margin_2 + margin_3;
+    let (net_leverage, notional_leverage) = if total_margin != 0 {
+        (
+            net as f32 / total_margin as f32,
+            notional as f32 / total_margin as f32,
+        )
+    } else {
+        (0.0, 0.0)
+    };
+
+    MarginFunctionResult {
+        net_qty,
+        notional_qty,
+        net,
+        notional,
+        stress_result,
+        net_intrinsic,
+        net_premium,
+        maturing_net_intrinsic,
+        maturing_premium,
+        maturing_liquidity,
+        total_margin,
+        net_leverage,
+        notional_leverage,
+    }
+}
+
+/// a hypothetical change of the user's position in an instrument
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct PositionDelta {
+    pub instrument: Pubkey,
+    /// net quantity change, positive for buying and negative for selling
+    pub quantity: i64,
+}
+
+/// the user's margin of an asset with the cross margin credit, as previewed by preview_margin
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarginPreview {
+    /// margin breakdown of the asset
+    pub margin: MarginFunctionResult,
+    /// cross margin credit over the user's assets
+    pub cross_margin_credit: u64,
+    /// total initial margin less the cross margin credit, which the orders are checked against
+    pub initial_margin: u64,
+}
+
+/// Calculate the margin of the positions in the instruments of the margin stress account
+pub fn calculate_margin(
+    margin_stress_account: &MarginStressAccount,
+    positions: Vec<i64>,
+) -> MarginFunctionResult {
+    if margin_stress_account.instruments.is_empty() {
+        return MarginFunctionResult::default();
+    }
+    let t = margin_stress_account.get_time_to_maturity();
+
+    // 37000 computing units
+    margin_function(
+        positions,
+        margin_stress_account.spot_price,
+        &t,
+        &margin_stress_account.option_price,
+        &margin_stress_account.intrinsic_value,
+        &margin_stress_account.option_price_delta_in_stress_price,
+    )
+}
 
-    return total_margin;
+/// Calculate the margin of the positions with the resting bids, in the worst case of
+/// the bids being filled under each scenario of the stress grid.
+///
+/// Under a scenario, a bid which loses value is counted as filled and a bid which gains value
+/// as not filled, so the stress result is the worst of any subset of the bids being filled.
+/// The net intrinsic value and premium of the positions only increase by filling bids,
+/// and the add ons of the soonest maturing options are the worse of none and all of the bids
+/// being filled.
+pub fn calculate_worst_case_margin(
+    margin_stress_account: &MarginStressAccount,
+    positions: Vec<i64>,
+    open_bids: &Vec<i64>,
+) -> MarginFunctionResult {
+    if open_bids.iter().all(|&qty| qty == 0) {
+        return calculate_margin(margin_stress_account, positions);
+    }
+
+    let positions_filled = positions
+        .iter()
+        .zip(open_bids.iter())
+        .map(|(qty, bid)| qty + bid)
+        .collect::<Vec<i64>>();
+
+    // worst portfolio value change across the scenarios, with the losing bids filled
+    let stress_result = stress_scenario_pnl(
+        &positions,
+        open_bids,
+        &margin_stress_account.option_price_delta_in_stress_price,
+    )
+    .into_iter()
+    .fold(i64::MAX, i64::min);
+
+    let mut margin_result = calculate_margin(margin_stress_account, positions);
+    let margin_result_filled = calculate_margin(margin_stress_account, positions_filled);
+
+    // the margin besides the stress and the net intrinsic value or premium component
+    let add_ons = |result: &MarginFunctionResult| {
+        result.total_margin
+            - (result.stress_result + result.net_intrinsic.min(result.net_premium)).min(0)
+    };
+    let margin_1 =
+        (stress_result + margin_result.net_intrinsic.min(margin_result.net_premium)).min(0);
+    let total_margin = margin_1 + add_ons(&margin_result).min(add_ons(&margin_result_filled));
+
+    margin_result.stress_result = stress_result;
+    margin_result.total_margin = total_margin;
+    if total_margin != 0 {
+        margin_result.net_leverage = margin_result.net as f32 / total_margin as f32;
+        margin_result.notional_leverage = margin_result.notional as f32 / total_margin as f32;
+    }
+    margin_result
+}
+
+/// Calculate the user's margin of the asset of the margin stress account,
+/// and keep the breakdown and the margin requirements in the user account
+pub fn update_user_margin(
+    user_account: &mut UserAccount,
+    margin_stress_account: &MarginStressAccount,
+) -> MarginFunctionResult {
+    let positions = user_account.get_positions(&margin_stress_account.instruments);
+    let open_bids = user_account.get_open_bid_quantities(&margin_stress_account.instruments);
+    let asset = margin_stress_account.asset;
+    user_account.position_values[asset as usize] =
+        positions_value(&positions, &margin_stress_account.option_price);
+    user_account.mark_to_market(
+        &margin_stress_account.instruments,
+        &margin_stress_account.option_price,
+    );
+    let margin_result = calculate_worst_case_margin(margin_stress_account, positions, &open_bids);
+    user_account.set_margin_report(asset, margin_result);
+    margin_result
+}
+
+/// market value of the positions marked to the option prices
+pub fn positions_value(positions: &Vec<i64>, option_price: &Vec<u64>) -> i64 {
+    positions
+        .iter()
+        .zip(option_price.iter())
+        .map(|(&qty, &price)| qty * price as i64)
+        .sum()
+}
+
+/// portfolio value change of the user under each scenario of the stress grid,
+/// in the worst case of the resting bids: a bid which loses value under the scenario
+/// is counted as filled, and a bid which gains value as not filled
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::stress_scenario_pnl;
+///
+/// let stress_price_change = vec![vec![-10i64, 0, 10], vec![5, 0, -5]];
+///
+/// assert_eq!(stress_scenario_pnl(&vec![1, 2], &vec![0, 0], &stress_price_change), vec![0, 0, 0]);
+/// assert_eq!(stress_scenario_pnl(&vec![1, 2], &vec![0, 1], &stress_price_change), vec![0, 0, -5]);
+/// ```
+pub fn stress_scenario_pnl(
+    user: &Vec<i64>,
+    open_bids: &Vec<i64>,
+    stress_price_change: &Vec<Vec<i64>>,
+) -> Vec<i64> {
+    let scenarios = stress_price_change.first().map_or(0, |row| row.len());
+    (0..scenarios)
+        .map(|j| {
+            user.iter()
+                .zip(open_bids.iter())
+                .zip(stress_price_change.iter())
+                .map(|((&qty, &bid), row)| qty * row[j] + (bid * row[j]).min(0))
+                .sum::<i64>()
+        })
+        .collect()
+}
+
+/// Margin credit for offsetting books in two correlated assets.
+///
+/// The two stress grids are combined into a joint grid, a pair of scenarios is
+/// allowed when the normalized spot shocks satisfy |x_b - correlation * x_a| <= 1 - |correlation|,
+/// so with a zero correlation every pair is allowed and no credit is given,
+/// and with a full correlation both assets move together.
+/// The credit is the worst joint loss less the sum of the worst losses of each asset.
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::cross_margin_credit;
+///
+/// let shocks = vec![-0.3f32, 0.0, 0.3];
+/// let long_btc = vec![-100i64, 0, 100];
+/// let short_eth = vec![100i64, 0, -100];
+///
+/// assert_eq!(cross_margin_credit(&long_btc, &shocks, &short_eth, &shocks, 1.0), 200);
+/// assert_eq!(cross_margin_credit(&long_btc, &shocks, &short_eth, &shocks, 0.0), 0);
+/// ```
+pub fn cross_margin_credit(
+    pnl_a: &[i64],
+    spot_shocks_a: &[f32],
+    pnl_b: &[i64],
+    spot_shocks_b: &[f32],
+    correlation: f32,
+) -> i64 {
+    if pnl_a.is_empty() || pnl_b.is_empty() {
+        return 0;
+    }
+
+    let normalize = |shocks: &[f32]| {
+        let max = shocks.iter().fold(0f32, |m, s| m.max(s.abs()));
+        shocks
+            .iter()
+            .map(|s| if max > 0.0 { s / max } else { 0.0 })
+            .collect::<Vec<f32>>()
+    };
+    let x_a = normalize(spot_shocks_a);
+    let x_b = normalize(spot_shocks_b);
+
+    let correlation = correlation.max(-1.0).min(1.0);
+    let band = 1.0 - correlation.abs() + 1e-6;
+
+    let mut joint_worst = i64::MAX;
+    for (i, a) in pnl_a.iter().enumerate() {
+        for (j, b) in pnl_b.iter().enumerate() {
+            if (x_b[j] - correlation * x_a[i]).abs() <= band {
+                joint_worst = joint_worst.min(a + b);
+            }
+        }
+    }
+
+    let worst_a = pnl_a.iter().copied().fold(i64::MAX, i64::min);
+    let worst_b = pnl_b.iter().copied().fold(i64::MAX, i64::min);
+    let separate_worst = worst_a.min(0) + worst_b.min(0);
+
+    (joint_worst.min(0) - separate_worst).max(0)
 }
 
 pub fn _calculate_margin(
@@ -286,8 +614,18 @@ pub fn _calculate_margin(
     //     .map(|(&p, &m)| (p as f32 * m).min(0.0))
     //     .sum::<f32>();
 
-    let stress_function_res =
-        stress_function(spot_price, strikes, iv, 0.0, 0.0, &t, STRESS, is_call, STEP);
+    let (spot_shocks, vol_shocks) = generate_stress_scenarios(STRESS, STEP, VOL_STRESS, VOL_STEP);
+    let stress_function_res = stress_function(
+        spot_price,
+        strikes,
+        iv,
+        0.0,
+        0.0,
+        &t,
+        is_call,
+        &spot_shocks,
+        &vol_shocks,
+    );
 
     // // 37000 computing units
     // let margin_result = margin_function(
@@ -304,146 +642,3 @@ pub fn _calculate_margin(
 
     // margin
 }
-
-/// Old version Margin function
-pub fn _margin_function(
-    user: Vec<i64>,
-    spot: f32,
-    t: &Vec<f32>,
-    price: Vec<Vec<f32>>,
-    intrinsic: Vec<Vec<f32>>,
-    stress_price_change: Vec<Vec<f32>>,
-) -> MarginFunctionResult {
-    // # calculates margin statistics for each user and his positions
-    // # totals
-    // # net contract position
-    let net_qty: i64 = user.iter().sum();
-    // #net notional contract position
-    let notional_qty: i64 = user.iter().map(|f| f.abs()).sum();
-    // # net notional position in USDT (assuming BTC/USDT or ETH/USDT spot price)
-    let net = net_qty as f32 * spot;
-    let notional = notional_qty as f32 * spot;
-
-    let user_vec: Vec<f32> = user.iter().map(|f| *f as f32).collect();
-    let user_matrix = Array::from_vec(user_vec.clone());
-
-    let stress_price_change_vec = stress_price_change.clone().into_iter().flatten().collect();
-    let stress_price_change_matrix = Array::from_shape_vec(
-        (stress_price_change.len(), stress_price_change[0].len()),
-        stress_price_change_vec,
-    )
-    .unwrap();
-
-    let new_matrix = user_matrix.dot(&stress_price_change_matrix);
-    let stress_result = new_matrix.iter().copied().fold(f32::NAN, f32::min);
-
-    let intrinsic_vec = intrinsic.clone().into_iter().flatten().collect();
-    let intrinsic_matrix =
-        Array::from_shape_vec((intrinsic.len(), intrinsic[0].len()), intrinsic_vec).unwrap();
-
-    let net_intrinsic_matrix = user_matrix.dot(&intrinsic_matrix);
-    let net_intrinsic = net_intrinsic_matrix[0];
-
-    let price_vec = price.clone().into_iter().flatten().collect();
-    let price_matrix = Array::from_shape_vec((price.len(), price[0].len()), price_vec).unwrap();
-
-    let net_premium_matrix = user_matrix.dot(&price_matrix);
-    let net_premium = net_premium_matrix[0];
-
-    let mut min_t: Vec<f32> = vec![];
-    let t_min = t.iter().copied().fold(f32::NAN, f32::min);
-
-    for e in t {
-        if *e == t_min {
-            min_t.push(1.)
-        } else {
-            min_t.push(0.)
-        }
-    }
-
-    let min_t_matrix = Array::from(min_t.clone());
-
-    let mut res: Vec<f32> = vec![];
-    for (i, e) in user_matrix.iter().enumerate() {
-        res.push(e * min_t_matrix[i])
-    }
-
-    let user_matrix = Array2::from_shape_vec((user_vec.len(), 1), user_vec).unwrap();
-    let min_t_matrix = Array2::from_shape_vec((min_t.len(), 1), min_t).unwrap();
-    let matrix1_1 = &user_matrix * &min_t_matrix;
-    let matrix1_1_1 = matrix1_1.t();
-
-    let matrix2 = &intrinsic_matrix * &min_t_matrix;
-
-    let maturing_net_intrinsic = matrix1_1_1
-        .iter()
-        .zip(matrix2.iter())
-        .map(|(a, b)| a * b)
-        .sum::<f32>();
-
-    let t_matrix = Array2::from_shape_vec((t.len(), 1), t.clone()).unwrap();
-    let matrix1 = (2. / (365. * &t_matrix + 1.)) * &user_matrix * &min_t_matrix;
-
-    println!("matrix1 {:?}", matrix1);
-
-    let matrix2 = &price_matrix * &min_t_matrix;
-
-    println!("matrix2 {:?}", matrix1);
-
-    // #calculates net premium
-    let maturing_premium = matrix1
-        .iter()
-        .zip(matrix2.iter())
-        .map(|(a, b)| a * b)
-        .sum::<f32>();
-
-    // #calcualtes liquidity add on
-    let matrix2 = &intrinsic_matrix * &min_t_matrix;
-    let maturing_liquidity = matrix1
-        .iter()
-        .zip(matrix2.iter())
-        .map(|(a, b)| a * b)
-        .sum::<f32>();
-
-    // # 1st margin component is a sum of 1) change in value after stress, and a minimum of net_intrincic/net premium value)
-    let margin_1 = (stress_result + net_intrinsic.min(net_premium)).min(0.0);
-
-    // # 2nd margin component is a liquidity add on for soon maturing options
-    let margin_2 = if maturing_liquidity < net_intrinsic && maturing_liquidity < 0. {
-        maturing_liquidity - net_intrinsic
-    } else {
-        0.
-    };
-
-    // # 3rd add on is premium add on for soon maturing options
-    let margin_3 = if maturing_premium < 0. {
-        maturing_premium
-    } else {
-        0.
-    };
-
-    println!("margin_1 {} ", margin_1);
-    println!("margin_2 {} ", margin_2);
-    println!("margin_3 {} ", margin_3);
-
-    // # total margin
-    let total_margin = margin_1 + margin_2 + margin_3;
-    let net_leverage = net / total_margin;
-    let notional_leverage = notional / total_margin;
-
-    return MarginFunctionResult {
-        net_qty,
-        notional_qty,
-        net,
-        notional,
-        stress_result,
-        net_intrinsic,
-        net_premium,
-        maturing_net_intrinsic,
-        maturing_premium,
-        maturing_liquidity,
-        total_margin,
-        net_leverage,
-        notional_leverage,
-    };
-}
@@ -11,6 +11,7 @@ pub mod option;
 pub mod oracle;
 pub mod orderbook_utils;
 pub mod orders;
+pub mod volatility;
 
 // pub use amm::*;
 pub use asset::*;
@@ -25,3 +26,4 @@ pub use oracle::*;
 pub use oracle::*;
 pub use orderbook_utils::*;
 pub use orders::*;
+pub use volatility::*;
@@ -1,10 +1,25 @@
 /*
 Code to manage loading IV and Spot data from Oracles like Switchboard,
 Pyth, etc.
+
+USDC quote conversion rule:
+All the prices used by strike generation, margin and settlement are USDC
+denominated, asset/usdc = asset/usd / usdc/usd, with the usdc/usd oracle price
+taken as it is - the peg is never assumed. When usdc/usd is out of
+1 +/- USDC_PEG_TOLERANCE, margin_stress_sync stops publishing margin stress
+(so no order can be placed) and no new instrument can be created, until usdc
+is back in the tolerance. Fund settlement always goes on at the actual usdc/usd.
  */
+use crate::constants::{MAX_ORACLE_AGE, USDC_PEG_TOLERANCE};
 use crate::financial::Asset;
 use crate::state::Exchange;
+#[cfg(feature = "testing")]
+use crate::state::MockOracleAccount;
+#[cfg(feature = "testing")]
+use crate::u_to_f_repr;
 use anchor_lang::prelude::{AccountInfo, Pubkey};
+#[cfg(feature = "testing")]
+use anchor_lang::AccountDeserialize;
 use switchboard_program::{get_aggregator_result, AggregatorState, RoundResult};
 
 /// load oracle data in the switchboard feed account
@@ -34,23 +49,87 @@ fn get_switchboard_value(feed_account: &AccountInfo) -> f32 {
     // .unwrap()
 }
 
+/// load the mock oracle account if the feed account is one,
+/// only available when the program is built with the `testing` feature
+#[cfg(feature = "testing")]
+fn load_mock_oracle(feed_account: &AccountInfo) -> Option<MockOracleAccount> {
+    if feed_account.owner != &crate::ID {
+        return None;
+    }
+    let data = feed_account.try_borrow_data().ok()?;
+    MockOracleAccount::try_deserialize(&mut &data[..]).ok()
+}
+
+/// load oracle data in the mock oracle account, in the same representation as switchboard
+#[cfg(feature = "testing")]
+fn get_mock_value(feed_account: &AccountInfo, oracle_data_type: OracleDataType) -> Option<f32> {
+    let mock_oracle = load_mock_oracle(feed_account)?;
+    match oracle_data_type {
+        OracleDataType::Spot => Some(u_to_f_repr!(mock_oracle.spot_price)),
+        // switchboard iv feeds are in percentage
+        OracleDataType::IV => Some(u_to_f_repr!(mock_oracle.iv) * 100f32),
+    }
+}
+
+/// get the update timestamp in the mock oracle account
+#[cfg(feature = "testing")]
+fn get_mock_timestamp(feed_account: &AccountInfo) -> Option<u64> {
+    load_mock_oracle(feed_account).map(|mock_oracle| mock_oracle.timestamp)
+}
+
+#[cfg(not(feature = "testing"))]
+fn get_mock_value(_feed_account: &AccountInfo, _oracle_data_type: OracleDataType) -> Option<f32> {
+    None
+}
+
+#[cfg(not(feature = "testing"))]
+fn get_mock_timestamp(_feed_account: &AccountInfo) -> Option<u64> {
+    None
+}
+
+/// load oracle data in the feed account, which is a switchboard feed account
+/// or a mock oracle account in testing builds
+fn get_feed_value(feed_account: &AccountInfo, oracle_data_type: OracleDataType) -> f32 {
+    if let Some(value) = get_mock_value(feed_account, oracle_data_type) {
+        return value;
+    }
+    get_switchboard_value(feed_account)
+}
+
 /// get asset iv from the switchboard feed account
 fn switchboard_get_iv(feed_account: &AccountInfo) -> f32 {
-    get_switchboard_value(feed_account).round() / 100f32
+    get_feed_value(feed_account, OracleDataType::IV).round() / 100f32
 }
 
 /// get the asset/usdc spot price
 fn switchboard_get_asset_to_usdc_spot(asset_feed: &AccountInfo, usdc_feed: &AccountInfo) -> f32 {
-    (get_switchboard_value(asset_feed) / get_switchboard_value(usdc_feed) * 100f32).round() / 100f32
+    convert_usd_to_usdc(
+        get_feed_value(asset_feed, OracleDataType::Spot),
+        get_feed_value(usdc_feed, OracleDataType::Spot),
+    )
 }
 
-/// get the asset/usd spot price
-/// !!! Important Note !!!
-/// If we take the usdc/usd into account, it may exceed the computing units limit
-/// in some cases because it reuqires about 18000 more units to do so.
-/// We may put this into a seprate tx in order to save computing units
+/// get the usdc/usd spot price
+fn switchboard_get_usdc_to_usd_spot(usdc_feed: &AccountInfo) -> f32 {
+    get_feed_value(usdc_feed, OracleDataType::Spot)
+}
+
+/// get the asset/usd spot price, without the usdc/usd conversion,
+/// e.g. for the margin stress which converts it with its cached usdc/usd price
 fn switchboard_get_asset_to_usd_spot(asset_feed: &AccountInfo) -> f32 {
-    (get_switchboard_value(asset_feed) * 100f32).round() / 100f32
+    (get_feed_value(asset_feed, OracleDataType::Spot) * 100f32).round() / 100f32
+}
+
+/// get the unix timestamp when the feed was last updated
+fn switchboard_get_timestamp(feed_account: &AccountInfo) -> u64 {
+    if let Some(timestamp) = get_mock_timestamp(feed_account) {
+        return timestamp;
+    }
+    let aggregator: AggregatorState = switchboard_program::get_aggregator(feed_account)
+        .expect("Couldn't build switchboard aggregator");
+    let round_result: RoundResult =
+        get_aggregator_result(&aggregator).expect("Couldn't get switchboard result");
+    round_result.round_open_timestamp.unwrap_or(0) as u64
 }
 
 /// get iv from oracle
@@ -68,7 +147,52 @@ pub fn get_asset_to_usd_spot(asset_feed: &AccountInfo) -> f32 {
     switchboard_get_asset_to_usd_spot(asset_feed)
 }
 
+/// get usdc/usd sopt price from oracle
+pub fn get_usdc_to_usd_spot(usdc_feed: &AccountInfo) -> f32 {
+    switchboard_get_usdc_to_usd_spot(usdc_feed)
+}
+
+/// convert an usd price to usdc with the usdc/usd price
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::convert_usd_to_usdc;
+///
+/// assert_eq!(convert_usd_to_usdc(50000f32, 1f32), 50000f32);
+/// assert_eq!(convert_usd_to_usdc(50000f32, 0.8f32), 62500f32);
+/// ```
+pub fn convert_usd_to_usdc(usd_price: f32, usdc_to_usd: f32) -> f32 {
+    (usd_price / usdc_to_usd * 100f32).round() / 100f32
+}
+
+/// check if the usdc/usd price is out of the peg tolerance
+pub fn is_usdc_depegged(usdc_to_usd: f32) -> bool {
+    (usdc_to_usd - 1f32).abs() > USDC_PEG_TOLERANCE
+}
+
+/// get the unix timestamp of the latest oracle update
+pub fn get_oracle_timestamp(feed_account: &AccountInfo) -> u64 {
+    switchboard_get_timestamp(feed_account)
+}
+
+/// check if oracle data updated at `oracle_timestamp` is older than MAX_ORACLE_AGE at `now`
+///
+/// # Examples
+/// ```rust
+/// use optifi::constants::MAX_ORACLE_AGE;
+/// use optifi::financial::is_oracle_stale;
+///
+/// assert!(!is_oracle_stale(1_000, 1_000 + MAX_ORACLE_AGE));
+/// assert!(is_oracle_stale(1_000, 1_001 + MAX_ORACLE_AGE));
+/// // a feed updated after `now` is not stale
+/// assert!(!is_oracle_stale(2_000, 1_000));
+/// ```
+pub fn is_oracle_stale(oracle_timestamp: u64, now: u64) -> bool {
+    now.saturating_sub(oracle_timestamp) > MAX_ORACLE_AGE
+}
+
 /// Oracle data type
+#[derive(Clone, Copy)]
 pub enum OracleDataType {
     Spot,
     IV,
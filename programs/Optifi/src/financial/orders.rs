@@ -17,3 +17,78 @@ impl Default for OrderSide {
         OrderSide::Bid
     }
 }
+
+#[assert_size(1)]
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum OrderType {
+    /// rests on the orderbook until it's filled or cancelled
+    Limit,
+    /// fills what it can right away, the rest is cancelled
+    ImmediateOrCancel,
+    /// only rests on the orderbook, it never takes liquidity
+    PostOnly,
+    /// fills in full right away, or the order fails
+    FillOrKill,
+}
+
+impl Default for OrderType {
+    fn default() -> OrderType {
+        OrderType::Limit
+    }
+}
+
+impl OrderType {
+    /// the serum order type the order is placed with,
+    /// fill-or-kill is placed as immediate-or-cancel and checked afterwards
+    pub fn to_serum_order_type(&self) -> serum_dex::matching::OrderType {
+        match self {
+            OrderType::Limit => serum_dex::matching::OrderType::Limit,
+            OrderType::ImmediateOrCancel | OrderType::FillOrKill => {
+                serum_dex::matching::OrderType::ImmediateOrCancel
+            }
+            OrderType::PostOnly => serum_dex::matching::OrderType::PostOnly,
+        }
+    }
+
+    /// whether nothing of the order is left on the orderbook after it's placed
+    pub fn is_immediate(&self) -> bool {
+        *self == OrderType::ImmediateOrCancel || *self == OrderType::FillOrKill
+    }
+}
+
+/// what happens when an order of the user would match a resting order of the same owner
+#[assert_size(1)]
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum SelfTradeMode {
+    /// both orders are decremented by the matched quantity, without a trade
+    DecrementTake,
+    /// the resting order is cancelled, the new order keeps matching
+    CancelProvide,
+    /// the whole transaction fails
+    AbortTransaction,
+}
+
+impl Default for SelfTradeMode {
+    fn default() -> SelfTradeMode {
+        SelfTradeMode::AbortTransaction
+    }
+}
+
+impl SelfTradeMode {
+    /// the serum self trade behavior the order is placed with
+    pub fn to_serum_self_trade_behavior(&self) -> serum_dex::instruction::SelfTradeBehavior {
+        match self {
+            SelfTradeMode::DecrementTake => {
+                serum_dex::instruction::SelfTradeBehavior::DecrementTake
+            }
+            SelfTradeMode::CancelProvide => {
+                serum_dex::instruction::SelfTradeBehavior::CancelProvide
+            }
+            SelfTradeMode::AbortTransaction => {
+                serum_dex::instruction::SelfTradeBehavior::AbortTransaction
+            }
+        }
+    }
+}
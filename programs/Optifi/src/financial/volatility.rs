@@ -0,0 +1,48 @@
+//! # IV term structure
+//! Interpolate the iv of a given maturity from iv feeds of a few tenors
+
+/// Interpolate the iv for a time to maturity from a term structure,
+/// linearly in total variance (iv^2 * t) between two tenors.
+///
+/// Total variance is kept non-decreasing in maturity, and the iv is
+/// extrapolated flat before the first and after the last tenor.
+///
+/// # Examples
+/// ```rust
+/// use optifi::financial::interpolate_iv;
+///
+/// let tenors = [7f32 / 365f32, 30f32 / 365f32, 90f32 / 365f32];
+/// let ivs = [0.9f32, 0.8f32, 0.7f32];
+///
+/// assert_eq!(interpolate_iv(&tenors, &ivs, 1f32 / 365f32), 0.9f32);
+/// assert_eq!(interpolate_iv(&tenors, &ivs, 1f32), 0.7f32);
+///
+/// let iv = interpolate_iv(&tenors, &ivs, 14f32 / 365f32);
+/// assert!(iv < 0.9f32 && iv > 0.8f32);
+/// ```
+pub fn interpolate_iv(tenors: &[f32], ivs: &[f32], t: f32) -> f32 {
+    let last = tenors.len() - 1;
+    if t <= tenors[0] {
+        return ivs[0];
+    }
+    if t >= tenors[last] {
+        return ivs[last];
+    }
+
+    let i = tenors.iter().position(|&tenor| t <= tenor).unwrap();
+
+    let w_0 = ivs[i - 1] * ivs[i - 1] * tenors[i - 1];
+    let w_1 = (ivs[i] * ivs[i] * tenors[i]).max(w_0);
+    let w = w_0 + (w_1 - w_0) * (t - tenors[i - 1]) / (tenors[i] - tenors[i - 1]);
+
+    (w / t).sqrt()
+}
+
+/// Get the iv for each time to maturity, with the term structure if there is one,
+/// or the single iv otherwise
+pub fn get_iv_by_maturity(tenors: &[f32], ivs: &[f32], iv: f32, t: &[f32]) -> Vec<f32> {
+    if tenors.is_empty() {
+        return vec![iv; t.len()];
+    }
+    t.iter().map(|&t| interpolate_iv(tenors, ivs, t)).collect()
+}
@@ -5,10 +5,11 @@ use crate::instructions::order::{
     mint_instrument_token_for_user,
     serum_utils::{serum_new_order, serum_prune_orders_for_user},
 };
-use crate::serum_utils::serum_settle_funds_for_user;
-use crate::state::{AmmAccount, AmmState, OptifiMarket};
+use crate::serum_utils::{serum_crosses_open_orders, serum_settle_funds_for_user};
+use crate::state::{AmmAccount, AmmState, Exchange, OptifiMarket, UserAccount};
 use crate::utils::{
-    get_serum_market_auth_pda, PREFIX_AMM_LIQUIDITY_AUTH, PREFIX_SERUM_MARKET_AUTH,
+    get_serum_market_auth_pda, get_serum_open_orders_pda, PREFIX_AMM_LIQUIDITY_AUTH,
+    PREFIX_SERUM_MARKET_AUTH,
 };
 use crate::{u_to_f_repr, uvec_to_fvec_repr};
 use anchor_lang::prelude::*;
@@ -21,7 +22,7 @@ use solana_program::log::sol_log_compute_units;
 #[instruction(order_limit: u16, instrument_index: u16)]
 pub struct UpdateAmmOrders<'info> {
     /// optifi exchange account
-    pub optifi_exchange: AccountInfo<'info>,
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
     /// the amm to update oders for
     #[account(mut, constraint = amm.optifi_exchange == optifi_exchange.key())]
     pub amm: ProgramAccount<'info, AmmAccount>,
@@ -76,6 +77,10 @@ pub struct UpdateAmmOrders<'info> {
     #[account(address = token::ID)]
     pub token_program: AccountInfo<'info>,
     pub rent: Sysvar<'info, Rent>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the user accounts which count the amm as the same owner, i.e.
+    // optifi_exchange.amm_linked_users, into ctx.remaining_accounts
+    // ====================================================================
 }
 
 /// Submit orders in order proposal - for executing crankers to call
@@ -86,7 +91,102 @@ pub fn handle_place_new_order(
     amm_authority_bump: u8,
     market_auth_bump: u8,
 ) -> ProgramResult {
+    check_amm_linked_self_trade(&ctx, instrument_index, amm_authority_bump)?;
+
     // ** hidden code **
 
     Ok(())
 }
+
+/// Users who link the amm count its orders as their own, so the proposed orders
+/// which would match a resting order of a linked user abort the transaction.
+/// Every user recorded as linking the amm on the exchange must be passed
+fn check_amm_linked_self_trade(
+    ctx: &Context<UpdateAmmOrders>,
+    instrument_index: u16,
+    amm_authority_bump: u8,
+) -> ProgramResult {
+    let linked_users = &ctx.accounts.optifi_exchange.amm_linked_users;
+    if linked_users.iter().any(|linked_user| {
+        !ctx.remaining_accounts
+            .iter()
+            .any(|user_account_info| user_account_info.key == linked_user)
+    }) {
+        return Err(ErrorCode::AmmLinkedUserMissing.into());
+    }
+    if linked_users.is_empty() {
+        return Ok(());
+    }
+    let exchange_key = ctx.accounts.optifi_exchange.key();
+    let serum_market = &ctx.accounts.serum_market;
+    let amm_authority = Pubkey::create_program_address(
+        &[
+            PREFIX_AMM_LIQUIDITY_AUTH.as_bytes(),
+            exchange_key.as_ref(),
+            &[amm_authority_bump],
+        ],
+        ctx.program_id,
+    )
+    .map_err(|_| ErrorCode::InvalidPDA)?;
+
+    let mut linked_open_orders = vec![];
+    for user_account_info in ctx.remaining_accounts.iter() {
+        if user_account_info.owner != ctx.program_id {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        let user_account = UserAccount::try_deserialize(&mut &user_account_info.data.borrow()[..])?;
+        if user_account.optifi_exchange != exchange_key
+            || !user_account.linked_owners.contains(&amm_authority)
+        {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        linked_open_orders.push(
+            get_serum_open_orders_pda(
+                &exchange_key,
+                serum_market.key,
+                user_account_info.key,
+                ctx.program_id,
+            )
+            .0,
+        );
+    }
+
+    // the most aggressive proposed bid and ask, in usdc (6 decimals repr) per contract,
+    // converted to pc lots per coin lot for serum
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let (coin_lot_size, pc_lot_size) = (market.coin_lot_size, market.pc_lot_size);
+    drop(market);
+    let proposal = &ctx.accounts.amm.proposals[instrument_index as usize];
+    let best_bid = proposal
+        .bid_orders_price
+        .iter()
+        .zip(proposal.bid_orders_size.iter())
+        .filter(|(_, &size)| size > 0)
+        .map(|(&price, _)| price)
+        .max();
+    let best_ask = proposal
+        .ask_orders_price
+        .iter()
+        .zip(proposal.ask_orders_size.iter())
+        .filter(|(_, &size)| size > 0)
+        .map(|(&price, _)| price)
+        .min();
+    for &(side, price) in [(Side::Bid, best_bid), (Side::Ask, best_ask)].iter() {
+        let price = match price {
+            Some(price) => price,
+            None => continue,
+        };
+        let limit = (price as u128 * coin_lot_size as u128 / pc_lot_size as u128) as u64;
+        if serum_crosses_open_orders(
+            serum_market,
+            &ctx.accounts.bids,
+            &ctx.accounts.asks,
+            side,
+            limit,
+            |open_orders| linked_open_orders.contains(open_orders),
+        )? {
+            return Err(ErrorCode::SelfTradeNotAllowed.into());
+        }
+    }
+    Ok(())
+}
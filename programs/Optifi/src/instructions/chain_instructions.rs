@@ -1,11 +1,13 @@
-use crate::constants::SECS_IN_STANDARD_YEAR;
+use crate::constants::{MAX_MARGIN_STRESS_AGE, SECS_IN_STANDARD_YEAR};
 use crate::errors::ErrorCode;
 use crate::financial::instruments::{ExpiryType, InstrumentType};
 use crate::financial::{
-    get_asset_to_usd_spot, get_iv, get_strikes, verify_switchboard_account, Asset, Chain, Duration,
+    convert_usd_to_usdc, get_asset_to_usd_spot, get_iv, get_oracle_timestamp, get_strikes,
+    is_oracle_stale, is_usdc_depegged, verify_switchboard_account, Asset, Chain, Duration,
     OracleDataType,
 };
-use crate::state::{Exchange, InstrumentCommon, InstrumentUnique};
+use crate::state::{Exchange, InstrumentCommon, InstrumentUnique, MarginStressAccount};
+use crate::u_to_f_repr;
 use crate::utils::PREFIX_INSTRUMENT;
 use anchor_lang::prelude::*;
 use solana_program::{log::sol_log_compute_units, pubkey::Pubkey};
@@ -69,6 +71,9 @@ pub struct CreateInstrument<'info> {
     // // oracle feed account for usdc spot price
     // #[account(constraint = verify_switchboard_account(Asset::USDC, OracleDataType::Spot, usdc_spot_price_oracle_feed.key, &optifi_exchange))]
     // pub usdc_spot_price_oracle_feed: AccountInfo<'info>,
+    /// margin stress account of the asset, which caches the usdc/usd spot price
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key() && margin_stress_account.asset as u8 == data.asset)]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
     /// Clock to get the timestamp
     pub clock: Sysvar<'info, Clock>,
 }
@@ -110,8 +115,30 @@ pub fn handler(ctx: Context<CreateInstrument>, _bump: u8, data: ChainData) -> Pr
     )) {
         return Err(ErrorCode::IncorrectOracleAccount.into());
     }
+    if is_oracle_stale(get_oracle_timestamp(asset_spot_price_oracle_feed), now)
+        || is_oracle_stale(get_oracle_timestamp(asset_iv_oracle_feed), now)
+    {
+        return Err(ErrorCode::StaleOracle.into());
+    }
+
+    // strikes are generated with the asset/usdc spot price, with the usdc/usd price
+    // cached in margin stress account, which must be synced recently
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    if now.saturating_sub(margin_stress_account.timestamp) > MAX_MARGIN_STRESS_AGE {
+        return Err(ErrorCode::TimeOut.into());
+    }
+    let usdc_spot_price = u_to_f_repr!(margin_stress_account.usdc_spot_price);
+    if usdc_spot_price == 0.0 {
+        return Err(ErrorCode::WrongState.into());
+    }
+    if is_usdc_depegged(usdc_spot_price) {
+        return Err(ErrorCode::UsdcDepegged.into());
+    }
 
-    let spot_price_from_oracle = get_asset_to_usd_spot(asset_spot_price_oracle_feed);
+    let spot_price_from_oracle = convert_usd_to_usdc(
+        get_asset_to_usd_spot(asset_spot_price_oracle_feed),
+        usdc_spot_price,
+    );
     let iv_from_oracle = get_iv(asset_iv_oracle_feed);
 
     // calculate the strikes
@@ -187,6 +214,7 @@ pub fn handler(ctx: Context<CreateInstrument>, _bump: u8, data: ChainData) -> Pr
         optifi_exchange.instrument_common.push(common);
         optifi_exchange.instrument_unique.push(vec![unique]);
     }
+    optifi_exchange.instruments_epoch[common.asset as usize] += 1;
 
     Ok(())
 }
@@ -210,14 +238,22 @@ pub fn clean(ctx: Context<CleanInstrument>) -> ProgramResult {
     let mut instrument_common = vec![];
     let mut instrument_unique = vec![];
 
+    let mut cleaned_assets = vec![];
+
     for (index, ic) in optifi_exchange.instrument_common.iter().enumerate() {
         if ic.expiry_date > now {
             instrument_common.push(*ic);
             instrument_unique.push(optifi_exchange.instrument_unique[index].clone());
+        } else {
+            cleaned_assets.push(ic.asset);
         }
     }
 
-    let len_2 = optifi_exchange.instrument_common.len();
+    let len_2 = instrument_common.len();
+
+    for asset in cleaned_assets {
+        optifi_exchange.instruments_epoch[asset as usize] += 1;
+    }
 
     msg!(
         "Clean {} expired instrument groups in exchange, remaining {} valid instrument groups",
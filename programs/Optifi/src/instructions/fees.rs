@@ -1,12 +1,13 @@
-use crate::constants::FEE;
-use anchor_lang::CpiContext;
+use crate::errors::ErrorCode;
+use crate::state::Exchange;
+use anchor_lang::prelude::*;
 use anchor_spl::token;
+use anchor_spl::token::accessor;
 use anchor_spl::token::Transfer;
-use solana_program::account_info::AccountInfo;
-use solana_program::entrypoint_deprecated::ProgramResult;
 
+/// transfer `amount` of accrued trading fees into the fee account
 pub fn pay_fees<'a, 'b, 'c, 'info>(
-    notional: u64,
+    amount: u64,
     token_program: AccountInfo<'info>,
     payer_account: AccountInfo<'info>,
     authority: AccountInfo<'info>,
@@ -23,7 +24,50 @@ pub fn pay_fees<'a, 'b, 'c, 'info>(
         Some(s) => transfer_context = CpiContext::new_with_signer(token_program, transfer, s),
         None => transfer_context = CpiContext::new(token_program, transfer),
     }
-    let amount = notional * FEE as u64;
     token::transfer(transfer_context, amount)?;
     Ok(())
 }
+
+#[derive(Accounts)]
+pub struct SetFeeVault<'info> {
+    /// optifi exchange account
+    #[account(mut, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// optifi exchange's authority
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+    /// the usdc token account which the trading fees are collected into
+    #[account(constraint = accessor::mint(&fee_vault)? == optifi_exchange.usdc_mint @ ErrorCode::InvalidAccount)]
+    pub fee_vault: AccountInfo<'info>,
+}
+
+/// Set the protocol fee vault of the exchange
+pub fn set_fee_vault_handler(ctx: Context<SetFeeVault>) -> ProgramResult {
+    let optifi_exchange = &mut ctx.accounts.optifi_exchange;
+    optifi_exchange.fee_vault = ctx.accounts.fee_vault.key();
+    msg!("fee vault is set to {}", optifi_exchange.fee_vault);
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetReferralFeeShare<'info> {
+    /// optifi exchange account
+    #[account(mut, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// optifi exchange's authority
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Set the share of the trading fees paid to the referrers, in bps
+pub fn set_referral_fee_share_handler(
+    ctx: Context<SetReferralFeeShare>,
+    share_bps: u64,
+) -> ProgramResult {
+    if share_bps > 10_000 {
+        return Err(ErrorCode::InvalidReferralFeeShare.into());
+    }
+    ctx.accounts.optifi_exchange.referral_fee_share_bps = share_bps;
+    msg!("referral fee share is set to {} bps", share_bps);
+    Ok(())
+}
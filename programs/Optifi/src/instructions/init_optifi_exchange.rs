@@ -1,9 +1,11 @@
+use crate::constants::DEFAULT_MARKET_ORDER_PRICE_BAND_BPS;
 use crate::errors::ErrorCode;
 use crate::financial::Asset;
 use crate::state::exchange::Exchange;
 use crate::state::OracleData;
 use crate::utils::PREFIX_OPTIFI_EXCHANGE;
 use anchor_lang::prelude::*;
+use anchor_spl::token::accessor;
 
 #[derive(Accounts)]
 #[instruction(bump:u8, data: InitializeExchangeData)]
@@ -20,6 +22,10 @@ pub struct InitializeOptiFiExchange<'info> {
     //#[account(constraint =  accessor::mint(&usdc_central_pool)? == data.usdc_mint && accessor::authority(&usdc_central_pool)?
     //== get_central_usdc_pool_auth_pda(&optifi_exchange.key(), program_id).0) ]
     pub usdc_central_pool: AccountInfo<'info>,
+    /// the usdc token account which the trading fees are collected into,
+    /// the authority can change it with set_fee_vault
+    #[account(constraint = accessor::mint(&fee_vault)? == data.usdc_mint @ ErrorCode::InvalidAccount)]
+    pub fee_vault: AccountInfo<'info>,
     #[account(mut, signer)]
     pub payer: AccountInfo<'info>,
     pub system_program: Program<'info, System>,
@@ -66,21 +72,26 @@ pub fn handler(
     optifi_exchange.owner = data.owner;
     optifi_exchange.usdc_mint = data.usdc_mint;
     optifi_exchange.usdc_central_pool = usdc_central_pool.key();
+    optifi_exchange.fee_vault = ctx.accounts.fee_vault.key();
+    optifi_exchange.market_order_price_band_bps = DEFAULT_MARKET_ORDER_PRICE_BAND_BPS;
 
     optifi_exchange.oracle.push(OracleData {
         asset: Asset::Bitcoin,
         spot_oracle: Some(data.btc_spot_oracle),
         iv_oracle: Some(data.btc_iv_oracle),
+        iv_term_oracles: vec![],
     });
     optifi_exchange.oracle.push(OracleData {
         asset: Asset::Ethereum,
         spot_oracle: Some(data.eth_spot_oracle),
         iv_oracle: Some(data.eth_iv_oracle),
+        iv_term_oracles: vec![],
     });
     optifi_exchange.oracle.push(OracleData {
         asset: Asset::USDC,
         spot_oracle: Some(data.usdc_spot_oracle),
         iv_oracle: None,
+        iv_term_oracles: vec![],
     });
 
     msg!("optifi exchange is initialized successfully");
@@ -1,15 +1,22 @@
-use crate::ceil;
-use crate::constants::LIQUIDATION;
 use crate::errors::ErrorCode;
-use crate::state::{LiquidationState, LiquidationStatus, MarginStressAccount, UserAccount};
+use crate::financial::margin::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
+use crate::state::{
+    Exchange, LiquidationState, LiquidationStatus, MarginStressAccount, UserAccount,
+};
 use anchor_lang::prelude::*;
-use anchor_spl::token::accessor;
 
 #[derive(Accounts)]
 pub struct InitializeLiquidation<'info> {
-    pub optifi_exchange: AccountInfo<'info>,
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
 
-    #[account(mut, constraint= user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
+    /// margin stress account of one of the user's assets, the user's margin of the asset
+    /// is recalculated with it before the user is checked
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key(),
+        constraint= user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
     pub user_account: ProgramAccount<'info, UserAccount>,
 
     /// user's margin account whose authority is user account(pda)
@@ -17,10 +24,55 @@ pub struct InitializeLiquidation<'info> {
 
     #[account(mut, constraint = liquidation_state.user_account == user_account.key())]
     pub liquidation_state: ProgramAccount<'info, LiquidationState>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // ====================================================================
 }
-/// Initialize liquidation for user
+/// Initialize liquidation for user, once the user's margin falls below the maintenance margin
 pub fn handler(ctx: Context<InitializeLiquidation>) -> ProgramResult {
-    // ** hidden code **
+    let user_account = &mut ctx.accounts.user_account;
+    let liquidation_state = &mut ctx.accounts.liquidation_state;
+
+    if user_account.is_in_liquidation {
+        return Err(ErrorCode::UserAlreadyInLiquidation.into());
+    }
+
+    // the user is only liquidated on a margin requirement of the current prices
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    margin_stress_account.check_available(
+        Clock::get()?.unix_timestamp as u64,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
+    update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+    user_account.update_health(&ctx.accounts.user_margin_account_usdc);
+
+    let equity = user_account.get_equity(&ctx.accounts.user_margin_account_usdc);
+    let maintanance_margin = user_account.get_maintanance_margin();
+
+    msg!(
+        "equity: {}, maintanance_margin: {}, initial_margin: {}",
+        equity,
+        maintanance_margin,
+        user_account.get_initial_margin()
+    );
+
+    // users between the initial and the maintenance margin can't open new positions,
+    // but are only liquidated below the maintenance margin
+    if equity >= maintanance_margin {
+        return Err(ErrorCode::UserNotLiquidatable.into());
+    }
+
+    user_account.is_in_liquidation = true;
+    liquidation_state.status = LiquidationStatus::CancelOrder;
 
     Ok(())
 }
@@ -1,11 +1,16 @@
-use crate::constants::{SECS_IN_STANDARD_YEAR, STEP, STRESS};
+use crate::constants::{
+    MARGIN_STRESS_COMPUTE_BUDGET, MARGIN_STRESS_FIXED_UNITS, MARGIN_STRESS_INTRINSIC_UNITS,
+    MARGIN_STRESS_ROW_UNITS, MARGIN_STRESS_SPOT_UNITS, SECS_IN_STANDARD_YEAR,
+};
 use crate::errors::ErrorCode;
-use crate::financial::stress_function;
+use crate::financial::{stress_function, stress_price_delta};
 
 use crate::state::MarginStressAccount;
 use crate::state::MarginStressState;
 use crate::Exchange;
-use crate::{f_to_i_repr, f_to_u_repr, fvec_to_ivec_repr, u_to_f_repr};
+use crate::{
+    f_to_i_repr, f_to_u_repr, fvec_to_ivec_repr, i_to_f_repr, ivec_to_fvec_repr, u_to_f_repr,
+};
 use anchor_lang::prelude::*;
 use solana_program::log::sol_log_compute_units;
 
@@ -18,6 +23,25 @@ pub struct CalculateMarginStressContext<'info> {
     pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
 }
 
+/// number of the scenarios from the start of `vol_shocks` which are priced within `units`,
+/// with the units they use, the scenarios of the same vol shock are priced together in a row
+fn scenarios_within_units(vol_shocks: &[f32], units: u64) -> (usize, u64) {
+    let mut used = 0;
+    for (i, vol_shock) in vol_shocks.iter().enumerate() {
+        let mut scenario_units = MARGIN_STRESS_SPOT_UNITS;
+        if i == 0 || vol_shocks[i - 1] != *vol_shock {
+            scenario_units += MARGIN_STRESS_ROW_UNITS;
+        }
+        if used + scenario_units > units {
+            return (i, used);
+        }
+        used += scenario_units;
+    }
+    (vol_shocks.len(), used)
+}
+
+/// Calculate the pending stress results of the next instruments, within the compute budget.
+/// Once every instrument is done, the pending results replace the current ones.
 pub fn handle(ctx: Context<CalculateMarginStressContext>) -> ProgramResult {
     if ctx.accounts.margin_stress_account.state != MarginStressState::Calculate {
         return Err(ErrorCode::WrongState.into());
@@ -26,24 +50,48 @@ pub fn handle(ctx: Context<CalculateMarginStressContext>) -> ProgramResult {
     let optifi_exchange = &ctx.accounts.optifi_exchange;
     let margin_stress_account = &mut ctx.accounts.margin_stress_account;
 
-    let now = margin_stress_account.timestamp;
-    let iv = u_to_f_repr!(margin_stress_account.iv);
-    let spot_price = u_to_f_repr!(margin_stress_account.spot_price);
+    let now = margin_stress_account.pending_timestamp;
+    let spot_price = u_to_f_repr!(margin_stress_account.pending_spot_price);
 
     sol_log_compute_units();
 
-    for _ in 0..2 {
-        let index = margin_stress_account
-            .flags
-            .iter()
-            .position(|&x| x == false)
-            .unwrap();
+    let spot_shocks: Vec<f32> =
+        ivec_to_fvec_repr!(margin_stress_account.pending_scenario_spot_shocks.clone());
+    let vol_shocks: Vec<f32> =
+        ivec_to_fvec_repr!(margin_stress_account.pending_scenario_vol_shocks.clone());
+
+    // every instrument is fully repriced at each node of the grid, which doesn't fit in
+    // one transaction, so each call prices as many rows of the grid as the compute budget
+    // allows: the current price of an instrument with its first scenarios, then the next ones
+    let scenarios = spot_shocks.len();
+    let mut units = MARGIN_STRESS_COMPUTE_BUDGET - MARGIN_STRESS_FIXED_UNITS;
+    loop {
+        let index = match margin_stress_account.flags.iter().position(|&x| x == false) {
+            Some(index) => index,
+            None => break,
+        };
+        let done = margin_stress_account.pending_option_price_delta_in_stress_price[index].len();
+        let price_units = if done == 0 {
+            MARGIN_STRESS_ROW_UNITS + MARGIN_STRESS_SPOT_UNITS + MARGIN_STRESS_INTRINSIC_UNITS
+        } else {
+            0
+        };
+        if price_units > units {
+            break;
+        }
+        let (count, scenario_units) =
+            scenarios_within_units(&vol_shocks[done..], units - price_units);
+        if count == 0 && done < scenarios {
+            break;
+        }
+        let end = done + count;
         //
         let instrument = margin_stress_account.instruments[index];
+        let iv = u_to_f_repr!(margin_stress_account.pending_expiry_iv[index]);
         let (instrument_data, strike, is_call) =
             optifi_exchange.get_instrument_data(&instrument).unwrap();
 
-        let time_to_maturity = instrument_data.expiry_date - now;
+        let time_to_maturity = instrument_data.expiry_date.saturating_sub(now);
         let time_to_maturity = time_to_maturity * 10_u64.pow(6) / SECS_IN_STANDARD_YEAR;
         let time_to_maturity = time_to_maturity as f32 / 10_u64.pow(6) as f32;
 
@@ -52,37 +100,66 @@ pub fn handle(ctx: Context<CalculateMarginStressContext>) -> ProgramResult {
         let t = vec![time_to_maturity];
 
         msg!(
-            "spot_price {}, strikes {:?}, iv {}, t {:?}, is_call {:?}",
+            "spot_price {}, strikes {:?}, iv {}, t {:?}, is_call {:?}, scenarios {}..{}",
             spot_price,
             strikes,
             iv,
             &t,
-            is_call
+            is_call,
+            done,
+            end
         );
 
-        //
-        let stress_function_res =
-            stress_function(spot_price, strikes, iv, 0.0, 0.0, &t, STRESS, is_call, STEP);
+        if done == 0 {
+            let stress_function_res = stress_function(
+                spot_price,
+                strikes.clone(),
+                iv,
+                0.0,
+                0.0,
+                &t,
+                is_call.clone(),
+                &vec![],
+                &vec![],
+            );
+            margin_stress_account.pending_option_price[index] =
+                f_to_u_repr!(stress_function_res.price[0][0].to_owned());
+            margin_stress_account.pending_intrinsic_value[index] =
+                f_to_u_repr!(stress_function_res.intrinsic_value[0][0].to_owned());
+        }
+
+        // the scenarios are priced against the stored current price
+        let price = vec![vec![u_to_f_repr!(
+            margin_stress_account.pending_option_price[index]
+        )]];
+        let stress_price = stress_price_delta(
+            spot_price,
+            &strikes,
+            iv,
+            0.0,
+            0.0,
+            &t,
+            &is_call,
+            &price,
+            &spot_shocks[done..end],
+            &vol_shocks[done..end],
+        );
+        let stress_price: Vec<i64> = fvec_to_ivec_repr!(stress_price[0].to_owned());
+        margin_stress_account.pending_option_price_delta_in_stress_price[index]
+            .extend(stress_price);
+        units -= price_units + scenario_units;
 
         // Done
-        margin_stress_account.flags[index] = true;
-        margin_stress_account.option_price[index] =
-            f_to_u_repr!(stress_function_res.price[0][0].to_owned());
-        margin_stress_account.intrinsic_value[index] =
-            f_to_u_repr!(stress_function_res.intrinsic_value[0][0].to_owned());
-        margin_stress_account.option_price_delta_in_stress_price[index] =
-            fvec_to_ivec_repr!(stress_function_res.stress_price_delta[0].to_owned());
+        if end == scenarios {
+            margin_stress_account.flags[index] = true;
+        }
 
         sol_log_compute_units();
     }
     //
     if margin_stress_account.flags.iter().all(|&flag| flag == true) {
-        msg!("The proposal calculation is finished, changing the margin_stress_account state...");
-
-        for flag in margin_stress_account.flags.iter_mut() {
-            *flag = false;
-        }
-        margin_stress_account.move_to_next_state();
+        msg!("The stress calculation is finished, the pending results replace the current ones");
+        margin_stress_account.publish_pending_results();
     }
 
     Ok(())
@@ -0,0 +1,191 @@
+use crate::errors::ErrorCode;
+use crate::financial::{cross_margin_credit, stress_scenario_pnl, update_user_margin, Asset};
+use crate::state::{AssetCorrelation, Exchange, MarginStressAccount, UserAccount};
+use crate::{i_to_f_repr, ivec_to_fvec_repr};
+use anchor_lang::prelude::*;
+use std::convert::TryFrom;
+
+#[derive(Accounts)]
+pub struct SetAssetCorrelation<'info> {
+    /// optifi exchange account
+    #[account(mut, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// optifi exchange's authority
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Set the spot correlation between two assets, which is used to give cross margin credit
+pub fn set_asset_correlation_handler(
+    ctx: Context<SetAssetCorrelation>,
+    asset_a: u8,
+    asset_b: u8,
+    correlation: i64,
+) -> ProgramResult {
+    let asset_a = Asset::try_from(asset_a).map_err(|_| ErrorCode::WrongAsset)?;
+    let asset_b = Asset::try_from(asset_b).map_err(|_| ErrorCode::WrongAsset)?;
+    if asset_a == Asset::USDC || asset_b == Asset::USDC {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+    if asset_a == asset_b || i_to_f_repr!(correlation).abs() > 1.0 {
+        return Err(ErrorCode::InvalidCorrelation.into());
+    }
+
+    let optifi_exchange = &mut ctx.accounts.optifi_exchange;
+    optifi_exchange.asset_correlations.retain(|c| {
+        !((c.asset_a == asset_a && c.asset_b == asset_b)
+            || (c.asset_a == asset_b && c.asset_b == asset_a))
+    });
+    optifi_exchange.asset_correlations.push(AssetCorrelation {
+        asset_a,
+        asset_b,
+        correlation,
+    });
+
+    msg!(
+        "set correlation between {:?} and {:?} to {}",
+        asset_a,
+        asset_b,
+        i_to_f_repr!(correlation)
+    );
+
+    Ok(())
+}
+
+/// Load the margin stress accounts passed in remaining accounts,
+/// each of them must be available, up to date and of a different asset
+pub fn load_margin_stress_accounts<'info>(
+    program_id: &Pubkey,
+    optifi_exchange: &ProgramAccount<'info, Exchange>,
+    accounts: &[AccountInfo<'info>],
+) -> Result<Vec<MarginStressAccount>, ProgramError> {
+    let now = Clock::get()?.unix_timestamp as u64;
+    let mut stress_accounts: Vec<MarginStressAccount> = vec![];
+    for account in accounts {
+        if account.owner != program_id {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        let stress_account = MarginStressAccount::try_deserialize(&mut &account.data.borrow()[..])?;
+        if stress_account.optifi_exchange != optifi_exchange.key() {
+            return Err(ErrorCode::UnauthorizedAccount.into());
+        }
+        stress_account.check_available(
+            now,
+            optifi_exchange.instruments_epoch[stress_account.asset as usize],
+        )?;
+        if stress_accounts
+            .iter()
+            .any(|s| s.asset == stress_account.asset)
+        {
+            return Err(ErrorCode::WrongAsset.into());
+        }
+        stress_accounts.push(stress_account);
+    }
+    Ok(stress_accounts)
+}
+
+/// Update the user's cross margin credit.
+///
+/// The margin stress accounts of the user's other assets are passed in `remaining_accounts`,
+/// their margin requirements are refreshed as well, so that the credit is always given
+/// against up to date requirements. The margin stress account of every asset the user
+/// has positions or resting bids in must be passed, otherwise the credit of the asset
+/// would be dropped, so it fails with InvalidAccount.
+pub fn update_cross_margin<'info>(
+    program_id: &Pubkey,
+    optifi_exchange: &ProgramAccount<'info, Exchange>,
+    user_account: &mut UserAccount,
+    margin_stress_account: &MarginStressAccount,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> ProgramResult {
+    if !user_account.cross_margin {
+        user_account.cross_margin_credit = 0;
+        return Ok(());
+    }
+
+    let other_stress_accounts =
+        load_margin_stress_accounts(program_id, optifi_exchange, remaining_accounts)?;
+    if other_stress_accounts
+        .iter()
+        .any(|s| s.asset == margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+    let missing_stress_account = user_account
+        .positions
+        .iter()
+        .filter(|p| p.get_quantity() != 0 || p.get_open_bid_quantity() > 0)
+        .filter_map(|p| optifi_exchange.get_instrument_asset(&p.instrument))
+        .any(|asset| {
+            asset != margin_stress_account.asset
+                && !other_stress_accounts.iter().any(|s| s.asset == asset)
+        });
+    if missing_stress_account {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+
+    for stress_account in other_stress_accounts.iter() {
+        update_user_margin(user_account, stress_account);
+    }
+
+    let mut stress_accounts: Vec<&MarginStressAccount> = vec![margin_stress_account];
+    stress_accounts.extend(other_stress_accounts.iter());
+
+    update_cross_margin_credit(optifi_exchange, user_account, &stress_accounts);
+
+    Ok(())
+}
+
+/// Calculate the cross margin credit of the user over the assets of the margin stress accounts,
+/// the margin requirement of each asset should be up to date
+pub fn update_cross_margin_credit(
+    optifi_exchange: &Exchange,
+    user_account: &mut UserAccount,
+    stress_accounts: &[&MarginStressAccount],
+) {
+    if !user_account.cross_margin {
+        user_account.cross_margin_credit = 0;
+        return;
+    }
+
+    // stress pnl and spot shocks of each asset
+    let scenarios = stress_accounts
+        .iter()
+        .map(|s| {
+            // the credit is given on the same worst case portfolio as the margin of the asset,
+            // with the losing bids filled
+            let positions = user_account.get_positions(&s.instruments);
+            let open_bids = user_account.get_open_bid_quantities(&s.instruments);
+            let pnl = stress_scenario_pnl(
+                &positions,
+                &open_bids,
+                &s.option_price_delta_in_stress_price,
+            );
+            let spot_shocks: Vec<f32> = ivec_to_fvec_repr!(s.scenario_spot_shocks);
+            (s.asset, pnl, spot_shocks)
+        })
+        .collect::<Vec<(Asset, Vec<i64>, Vec<f32>)>>();
+
+    // each asset is offset against at most one other asset, so no credit is counted twice
+    let mut paired: Vec<Asset> = vec![];
+    let mut credit = 0;
+    for (i, (asset_a, pnl_a, shocks_a)) in scenarios.iter().enumerate() {
+        for (asset_b, pnl_b, shocks_b) in scenarios.iter().skip(i + 1) {
+            if paired.contains(asset_a) || paired.contains(asset_b) {
+                continue;
+            }
+            if let Some(correlation) = optifi_exchange.get_asset_correlation(*asset_a, *asset_b) {
+                let pair_credit =
+                    cross_margin_credit(pnl_a, shocks_a, pnl_b, shocks_b, correlation) as u64;
+                let pair_requirement = user_account.amount_to_reserve[*asset_a as usize]
+                    + user_account.amount_to_reserve[*asset_b as usize];
+                credit += pair_credit.min(pair_requirement);
+                paired.push(*asset_a);
+                paired.push(*asset_b);
+            }
+        }
+    }
+
+    msg!("cross margin credit: {}", credit);
+    user_account.cross_margin_credit = credit;
+}
@@ -1,4 +1,5 @@
-
+use crate::constants::{MARGIN_STRESS_SPARE_INSTRUMENTS, MAX_ACCOUNT_SPACE, MAX_STRESS_SCENARIOS};
+use crate::errors::ErrorCode;
 use crate::{state::MarginStressAccount, financial::Asset};
 use crate::Exchange;
 use anchor_lang::prelude::*;
@@ -11,6 +12,9 @@ use std::convert::TryFrom;
 #[derive(Accounts, Clone)]
 #[instruction(bump: u8,asset:u8)]
 pub struct InitMarginStressContext<'info> {
+    /// the listed instruments of the asset must fit in a margin stress account of MAX_ACCOUNT_SPACE
+    #[account(constraint = MarginStressAccount::space(listed_instruments(&optifi_exchange, asset), MAX_STRESS_SCENARIOS)
+        <= MAX_ACCOUNT_SPACE @ ErrorCode::MarginStressAccountTooSmall)]
     pub optifi_exchange: ProgramAccount<'info, Exchange>,
 
     #[account(init, 
@@ -19,16 +23,35 @@ pub struct InitMarginStressContext<'info> {
             optifi_exchange.key().as_ref(),
             &[asset],
         ],
-        payer=payer, bump=bump, space=3000)]
+        payer=payer, bump=bump, space=margin_stress_space(&optifi_exchange, asset))]
     pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
 
 
-    #[account(signer)]
+    #[account(mut, signer)]
     pub payer: AccountInfo<'info>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
 }
 
+/// number of the instruments currently listed for the asset
+pub fn listed_instruments(optifi_exchange: &Exchange, asset: u8) -> usize {
+    match Asset::try_from(asset) {
+        Ok(asset) => optifi_exchange.get_instrument_data_with_asset(asset).0.len(),
+        Err(_) => 0,
+    }
+}
+
+/// space for the instruments currently listed for the asset plus some spare ones,
+/// with the max number of stress scenarios. Only the spare instruments are cut to fit
+/// in MAX_ACCOUNT_SPACE, the listed instruments are checked to fit at init
+pub fn margin_stress_space(optifi_exchange: &Exchange, asset: u8) -> usize {
+    MarginStressAccount::space(
+        listed_instruments(optifi_exchange, asset) + MARGIN_STRESS_SPARE_INSTRUMENTS,
+        MAX_STRESS_SCENARIOS,
+    )
+    .min(MAX_ACCOUNT_SPACE)
+}
+
 pub fn handle(ctx: Context<InitMarginStressContext>,bump:u8,asset:u8) -> ProgramResult {
     let optifi_exchange = &ctx.accounts.optifi_exchange;
     let margin_stress_account = &mut ctx.accounts.margin_stress_account;
@@ -40,20 +63,7 @@ pub fn handle(ctx: Context<InitMarginStressContext>,bump:u8,asset:u8) -> Program
 
     margin_stress_account.asset=asset;
 
-    let (instrument_pubkey, strikes, is_call, expiry_date) = optifi_exchange.get_instrument_data_with_asset(asset);
-
-    margin_stress_account.instruments= instrument_pubkey;
-    margin_stress_account.strikes  = strikes;
-    margin_stress_account.is_call = is_call;
-    margin_stress_account.expiry_date= expiry_date;
-
-    let len = margin_stress_account.instruments.len();
-
-    margin_stress_account.flags = vec![false;len];
-    margin_stress_account.option_price = vec![0;len];
-    margin_stress_account.intrinsic_value = vec![0;len];
-    margin_stress_account.option_price_delta_in_stress_price = vec![vec![];len];
-
+    margin_stress_account.rebuild_instruments(optifi_exchange);
 
     Ok(())
 }
@@ -1,7 +1,13 @@
 pub mod calculate;
+pub mod cross_margin;
 pub mod initialize;
+pub mod resync;
+pub mod risk_parameter;
 pub mod sync;
 
 pub use calculate::*;
+pub use cross_margin::*;
 pub use initialize::*;
+pub use resync::*;
+pub use risk_parameter::*;
 pub use sync::*;
@@ -0,0 +1,149 @@
+use crate::constants::{MAX_ACCOUNT_SPACE, MAX_STRESS_SCENARIOS};
+use crate::errors::ErrorCode;
+use crate::state::{Exchange, MarginStressAccount};
+use crate::utils::PREFIX_MARGIN_STRESS;
+use anchor_lang::prelude::*;
+use solana_program::program::{invoke, invoke_signed};
+use solana_program::system_instruction;
+
+use super::{listed_instruments, margin_stress_space};
+
+#[derive(Accounts)]
+pub struct ResyncMarginStressContext<'info> {
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    /// the margin stress account to rebuild, it's read and written by the handler,
+    /// as it's left without data when it's moved into a larger account
+    #[account(mut)]
+    pub margin_stress_account: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // when the listed instruments don't fit in the margin stress account anymore,
+    // pass the new margin stress account, the payer of its rent and the system
+    // program into ctx.remaining_accounts. The new account is the pda of
+    // [PREFIX_MARGIN_STRESS, optifi_exchange, asset, margin_stress_account]
+    // ====================================================================
+}
+
+/// Rebuild the instrument list of a margin stress account after instruments of its asset
+/// were listed or cleaned, the account then has to go through sync and calculate again.
+///
+/// If the instruments don't fit in the account anymore, it's copied into a larger account
+/// at a new pda, and the old account is left without data, so that it's neither read as
+/// a margin stress account nor initialized again at its address.
+pub fn resync_handler(ctx: Context<ResyncMarginStressContext>) -> ProgramResult {
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let margin_stress_info = &ctx.accounts.margin_stress_account;
+
+    if margin_stress_info.owner != ctx.program_id {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+    let mut margin_stress_account =
+        MarginStressAccount::try_deserialize(&mut &margin_stress_info.data.borrow()[..])?;
+    if margin_stress_account.optifi_exchange != optifi_exchange.key() {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+
+    // only an out of sync account is rebuilt, so that the margin stress can't be halted at will
+    let asset = margin_stress_account.asset as u8;
+    if margin_stress_account.instruments_epoch == optifi_exchange.instruments_epoch[asset as usize]
+    {
+        return Err(ErrorCode::MarginStressInSync.into());
+    }
+
+    let instruments = listed_instruments(optifi_exchange, asset);
+    let space = MarginStressAccount::space(instruments, MAX_STRESS_SCENARIOS);
+    if space > MAX_ACCOUNT_SPACE {
+        msg!(
+            "margin stress account can't hold {} instruments in {} bytes",
+            instruments,
+            MAX_ACCOUNT_SPACE
+        );
+        return Err(ErrorCode::MarginStressAccountTooSmall.into());
+    }
+
+    margin_stress_account.rebuild_instruments(optifi_exchange);
+
+    if space <= margin_stress_info.data_len() {
+        return margin_stress_account
+            .try_serialize(&mut &mut margin_stress_info.data.borrow_mut()[..]);
+    }
+
+    // the instruments don't fit anymore, so the account is moved into a larger one
+    let (new_margin_stress_info, payer, system_program) = match ctx.remaining_accounts {
+        [new_margin_stress_info, payer, system_program, ..] => {
+            (new_margin_stress_info, payer, system_program)
+        }
+        _ => return Err(ErrorCode::MarginStressAccountTooSmall.into()),
+    };
+    let exchange_key = optifi_exchange.key();
+    let old_key = margin_stress_info.key();
+    let (new_key, bump) = Pubkey::find_program_address(
+        &[
+            PREFIX_MARGIN_STRESS.as_bytes(),
+            exchange_key.as_ref(),
+            &[asset],
+            old_key.as_ref(),
+        ],
+        ctx.program_id,
+    );
+    if new_margin_stress_info.key() != new_key {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+    let signer_seeds: &[&[u8]] = &[
+        PREFIX_MARGIN_STRESS.as_bytes(),
+        exchange_key.as_ref(),
+        &[asset],
+        old_key.as_ref(),
+        &[bump],
+    ];
+
+    // the new account is created as anchor's init does, also when lamports were sent to it
+    let new_space = margin_stress_space(optifi_exchange, asset);
+    let lamports = Rent::get()?.minimum_balance(new_space);
+    if new_margin_stress_info.lamports() == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                &new_key,
+                lamports,
+                new_space as u64,
+                ctx.program_id,
+            ),
+            &[
+                payer.clone(),
+                new_margin_stress_info.clone(),
+                system_program.clone(),
+            ],
+            &[signer_seeds],
+        )?;
+    } else {
+        let required_lamports = lamports.saturating_sub(new_margin_stress_info.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, &new_key, required_lamports),
+                &[
+                    payer.clone(),
+                    new_margin_stress_info.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(&new_key, new_space as u64),
+            &[new_margin_stress_info.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(&new_key, ctx.program_id),
+            &[new_margin_stress_info.clone(), system_program.clone()],
+            &[signer_seeds],
+        )?;
+    }
+
+    margin_stress_account.bump = bump;
+    margin_stress_account.try_serialize(&mut &mut new_margin_stress_info.data.borrow_mut()[..])?;
+    margin_stress_info.data.borrow_mut().fill(0);
+    msg!("margin stress account is moved to {}", new_key);
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use crate::constants::{MAX_STRESS_SCENARIOS, STEP, STRESS, VOL_STEP, VOL_STRESS};
+use crate::errors::ErrorCode;
+use crate::financial::{generate_stress_scenarios, Asset};
+use crate::state::{Exchange, RiskParameterAccount, StressScenario};
+use crate::utils::PREFIX_RISK_PARAMETER;
+use crate::{f_to_i_repr, i_to_f_repr};
+use anchor_lang::prelude::*;
+use std::convert::TryFrom;
+
+#[derive(Accounts)]
+#[instruction(bump: u8, asset: u8)]
+pub struct InitRiskParameterContext<'info> {
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    #[account(init,
+        seeds=[
+            PREFIX_RISK_PARAMETER.as_bytes(),
+            optifi_exchange.key().as_ref(),
+            &[asset],
+        ],
+        payer=authority, bump=bump, space=8+32+1+1+4+MAX_STRESS_SCENARIOS*16)]
+    pub risk_parameter_account: ProgramAccount<'info, RiskParameterAccount>,
+
+    /// optifi exchange's authority
+    #[account(mut, signer, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SetStressScenariosContext<'info> {
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    #[account(mut, constraint = risk_parameter_account.optifi_exchange == optifi_exchange.key())]
+    pub risk_parameter_account: ProgramAccount<'info, RiskParameterAccount>,
+
+    /// optifi exchange's authority
+    #[account(signer, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Initialize the risk parameters of an asset,
+/// the stress scenarios start as the spot x vol grid of the constants
+pub fn init_handler(ctx: Context<InitRiskParameterContext>, bump: u8, asset: u8) -> ProgramResult {
+    let asset = Asset::try_from(asset).map_err(|_| ErrorCode::WrongAsset)?;
+    if asset == Asset::USDC {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
+    let risk_parameter_account = &mut ctx.accounts.risk_parameter_account;
+    risk_parameter_account.optifi_exchange = ctx.accounts.optifi_exchange.key();
+    risk_parameter_account.bump = bump;
+    risk_parameter_account.asset = asset;
+
+    let (spot_shocks, vol_shocks) = generate_stress_scenarios(STRESS, STEP, VOL_STRESS, VOL_STEP);
+    risk_parameter_account.scenarios = spot_shocks
+        .iter()
+        .zip(vol_shocks.iter())
+        .map(|(&spot_shock, &vol_shock)| StressScenario {
+            spot_shock: f_to_i_repr!(spot_shock),
+            vol_shock: f_to_i_repr!(vol_shock),
+        })
+        .collect();
+
+    Ok(())
+}
+
+/// Set the stress scenarios of an asset, they're used from the next margin_stress_sync
+pub fn set_stress_scenarios_handler(
+    ctx: Context<SetStressScenariosContext>,
+    scenarios: Vec<StressScenario>,
+) -> ProgramResult {
+    // a shock can't take the spot price or the iv to zero or below
+    if scenarios.is_empty()
+        || scenarios.len() > MAX_STRESS_SCENARIOS
+        || scenarios
+            .iter()
+            .any(|s| i_to_f_repr!(s.spot_shock) <= -1.0 || i_to_f_repr!(s.vol_shock) <= -1.0)
+    {
+        return Err(ErrorCode::InvalidStressScenarios.into());
+    }
+
+    let risk_parameter_account = &mut ctx.accounts.risk_parameter_account;
+    msg!(
+        "set {} stress scenarios for {:?}",
+        scenarios.len(),
+        risk_parameter_account.asset
+    );
+    risk_parameter_account.scenarios = scenarios;
+
+    Ok(())
+}
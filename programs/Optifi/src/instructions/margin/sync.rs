@@ -1,10 +1,15 @@
+use crate::constants::{
+    MARGIN_STRESS_CACHE_AGE, MAX_MARGIN_STRESS_AGE, SECS_IN_STANDARD_YEAR, SMALL_IV_MOVE,
+    SMALL_SPOT_MOVE,
+};
 use crate::errors::ErrorCode;
 use crate::financial::{
-    get_asset_to_usdc_spot, get_iv, verify_switchboard_account, Asset, OracleDataType,
+    convert_usd_to_usdc, get_asset_to_usd_spot, get_iv, get_iv_by_maturity, get_oracle_timestamp,
+    get_usdc_to_usd_spot, is_usdc_depegged, verify_switchboard_account, Asset, OracleDataType,
 };
 
-use crate::f_to_u_repr;
-use crate::state::MarginStressAccount;
+use crate::{f_to_u_repr, fvec_to_uvec_repr, u_to_f_repr};
+use crate::state::{MarginStressAccount, RiskParameterAccount};
 use crate::state::MarginStressState;
 use crate::Exchange;
 use anchor_lang::prelude::*;
@@ -17,6 +22,11 @@ pub struct SyncMarginStressContext<'info> {
     #[account(mut, constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
     pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
 
+    /// risk parameters of the asset, to get the stress scenarios
+    #[account(constraint = risk_parameter_account.optifi_exchange == optifi_exchange.key()
+        && risk_parameter_account.asset == margin_stress_account.asset)]
+    pub risk_parameter_account: ProgramAccount<'info, RiskParameterAccount>,
+
     // Oracle to get the spot price
     pub asset_feed: AccountInfo<'info>,
     pub usdc_feed: AccountInfo<'info>,
@@ -24,15 +34,16 @@ pub struct SyncMarginStressContext<'info> {
 
     // Clock to get the timestamp
     pub clock: Sysvar<'info, Clock>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the iv term structure oracle accounts of the asset, in the same
+    // order as they are configured in optifi exchange, into ctx.remaining_accounts
+    // ====================================================================
 }
 
+/// Sync the oracle data of the margin stress. The current stress results stay available
+/// on small moves, otherwise the pending results are calculated from the new data
+/// while the current ones stay available, until they are replaced or time out
 pub fn handle(ctx: Context<SyncMarginStressContext>) -> ProgramResult {
-    if ctx.accounts.margin_stress_account.state == MarginStressState::Sync {
-    } else if ctx.accounts.margin_stress_account.state == MarginStressState::Available {
-    } else {
-        return Err(ErrorCode::WrongState.into());
-    }
-
     let optifi_exchange = &ctx.accounts.optifi_exchange;
     let margin_stress_account = &mut ctx.accounts.margin_stress_account;
 
@@ -42,6 +53,11 @@ pub fn handle(ctx: Context<SyncMarginStressContext>) -> ProgramResult {
 
     let asset = margin_stress_account.asset;
 
+    // instruments were listed or cleaned since the instrument list was built
+    if margin_stress_account.instruments_epoch != optifi_exchange.instruments_epoch[asset as usize] {
+        return Err(ErrorCode::MarginStressOutOfSync.into());
+    }
+
     if !(verify_switchboard_account(asset, OracleDataType::Spot, asset_feed.key, optifi_exchange)
         && verify_switchboard_account(
             Asset::USDC,
@@ -54,18 +70,122 @@ pub fn handle(ctx: Context<SyncMarginStressContext>) -> ProgramResult {
         return Err(ErrorCode::IncorrectOracleAccount.into());
     }
 
-    let spot_price = get_asset_to_usdc_spot(asset_feed, usdc_feed);
-    let iv = get_iv(iv_feed);
+    let iv_term_oracles = optifi_exchange.get_iv_term_oracles(asset);
+    if ctx.remaining_accounts.len() < iv_term_oracles.len()
+        || iv_term_oracles
+            .iter()
+            .zip(ctx.remaining_accounts.iter())
+            .any(|(term_oracle, feed)| term_oracle.oracle != feed.key())
+    {
+        return Err(ErrorCode::IncorrectOracleAccount.into());
+    }
+
+    // read usdc/usd only once, and cache it for the other instructions
+    let usdc_spot_price = get_usdc_to_usd_spot(usdc_feed);
     let now = Clock::get().unwrap().unix_timestamp as u64;
 
-    margin_stress_account.spot_price = f_to_u_repr!(spot_price);
-    margin_stress_account.iv = f_to_u_repr!(iv);
-    margin_stress_account.timestamp = now;
+    margin_stress_account.usdc_spot_price = f_to_u_repr!(usdc_spot_price);
 
-    for flag in margin_stress_account.flags.iter_mut() {
-        *flag = false;
+    if is_usdc_depegged(usdc_spot_price) {
+        msg!(
+            "usdc/usd {} is out of the peg tolerance, margin stress is halted",
+            usdc_spot_price
+        );
+        margin_stress_account.timestamp = now;
+        margin_stress_account.calculated_timestamp = 0;
+        margin_stress_account.state = MarginStressState::Sync;
+        return Ok(());
     }
-    margin_stress_account.state = MarginStressState::Calculate;
+
+    let spot_price = convert_usd_to_usdc(get_asset_to_usd_spot(asset_feed), usdc_spot_price);
+    let iv = get_iv(iv_feed);
+
+    // interpolate the iv term structure to each instrument's expiry date
+    let tenors = iv_term_oracles
+        .iter()
+        .map(|term_oracle| term_oracle.tenor as f32 / SECS_IN_STANDARD_YEAR as f32)
+        .collect::<Vec<f32>>();
+    let term_ivs = ctx
+        .remaining_accounts
+        .iter()
+        .take(iv_term_oracles.len())
+        .map(|feed| get_iv(feed))
+        .collect::<Vec<f32>>();
+    // the prices are as old as the oldest of the oracles
+    let oracle_timestamp = [asset_feed, usdc_feed, iv_feed]
+        .iter()
+        .copied()
+        .chain(ctx.remaining_accounts.iter().take(iv_term_oracles.len()))
+        .map(|feed| get_oracle_timestamp(feed))
+        .min()
+        .unwrap_or(0);
+    let t = margin_stress_account
+        .expiry_date
+        .iter()
+        .map(|&d| d.saturating_sub(now) as f32 / SECS_IN_STANDARD_YEAR as f32)
+        .collect::<Vec<f32>>();
+    let expiry_iv = get_iv_by_maturity(&tenors, &term_ivs, iv, &t);
+
+    msg!(
+        "spot_price {}, usdc_spot_price {}, iv {}, term ivs {:?}, expiry ivs {:?}",
+        spot_price,
+        usdc_spot_price,
+        iv,
+        term_ivs,
+        expiry_iv
+    );
+
+    // the scenarios of the same vol shock are repriced together, so keep them next to each other
+    let mut scenarios = ctx.accounts.risk_parameter_account.scenarios.clone();
+    scenarios.sort_by_key(|s| s.vol_shock);
+    let scenario_spot_shocks: Vec<i64> = scenarios.iter().map(|s| s.spot_shock).collect();
+    let scenario_vol_shocks: Vec<i64> = scenarios.iter().map(|s| s.vol_shock).collect();
+
+    // keep the calculated results available while the market barely moves,
+    // so that they don't have to be recalculated on every refresh
+    let is_small_move = |last: u64, current: f32, tolerance: f32| {
+        let last = u_to_f_repr!(last);
+        last > 0.0 && ((current - last) / last).abs() <= tolerance
+    };
+    if margin_stress_account.has_results()
+        && now.saturating_sub(margin_stress_account.calculated_timestamp) <= MARGIN_STRESS_CACHE_AGE
+        && is_small_move(margin_stress_account.spot_price, spot_price, SMALL_SPOT_MOVE)
+        && is_small_move(margin_stress_account.iv, iv, SMALL_IV_MOVE)
+        && margin_stress_account.expiry_iv.len() == expiry_iv.len()
+        && margin_stress_account
+            .expiry_iv
+            .iter()
+            .zip(expiry_iv.iter())
+            .all(|(&last, &current)| is_small_move(last, current, SMALL_IV_MOVE))
+        && margin_stress_account.scenario_spot_shocks == scenario_spot_shocks
+        && margin_stress_account.scenario_vol_shocks == scenario_vol_shocks
+    {
+        msg!("small move since the last calculation, keep the stress results available");
+        margin_stress_account.timestamp = now;
+        margin_stress_account.oracle_timestamp = oracle_timestamp;
+        return Ok(());
+    }
+
+    // a pending calculation goes on, unless it's too old to be used once complete,
+    // so that it isn't restarted by every sync in a moving market
+    if margin_stress_account.state == MarginStressState::Calculate
+        && now.saturating_sub(margin_stress_account.pending_timestamp) <= MAX_MARGIN_STRESS_AGE
+    {
+        msg!("the pending stress results are being calculated");
+        return Ok(());
+    }
+
+    // the pending results are calculated in several margin_stress_calculate,
+    // the current results stay available until they are replaced or time out
+    margin_stress_account.start_calculation(
+        f_to_u_repr!(spot_price),
+        f_to_u_repr!(iv),
+        fvec_to_uvec_repr!(expiry_iv),
+        scenario_spot_shocks,
+        scenario_vol_shocks,
+        now,
+        oracle_timestamp,
+    );
 
     Ok(())
 }
@@ -0,0 +1,72 @@
+use crate::errors::ErrorCode;
+use crate::state::MockOracleAccount;
+use anchor_lang::prelude::*;
+use std::mem::size_of;
+
+#[derive(Default, AnchorSerialize, AnchorDeserialize)]
+pub struct MockOracleData {
+    /// spot price of the asset in usd (f_to_u_repr)
+    pub spot_price: u64,
+    /// implied volatility of the asset, 0.8 means 80% (f_to_u_repr)
+    pub iv: u64,
+    /// unix timestamp of the update
+    pub timestamp: u64,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMockOracle<'info> {
+    /// the mock oracle feed to be initialized
+    #[account(init, payer=payer, space=8+size_of::<MockOracleAccount>())]
+    pub mock_oracle: ProgramAccount<'info, MockOracleAccount>,
+    /// the authority which is allowed to update the feed
+    pub authority: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub payer: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SetMockOracle<'info> {
+    #[account(mut, has_one = authority)]
+    pub mock_oracle: ProgramAccount<'info, MockOracleAccount>,
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Initialize a mock oracle feed, only available with the `testing` feature
+pub fn init_handler(ctx: Context<InitializeMockOracle>, data: MockOracleData) -> ProgramResult {
+    if !cfg!(feature = "testing") {
+        return Err(ErrorCode::MockOracleDisabled.into());
+    }
+
+    let mock_oracle = &mut ctx.accounts.mock_oracle;
+    mock_oracle.authority = ctx.accounts.authority.key();
+    mock_oracle.spot_price = data.spot_price;
+    mock_oracle.iv = data.iv;
+    mock_oracle.timestamp = data.timestamp;
+
+    msg!("mock oracle is initialized successfully");
+    Ok(())
+}
+
+/// Set the spot price, iv and timestamp of a mock oracle feed,
+/// only available with the `testing` feature
+pub fn set_handler(ctx: Context<SetMockOracle>, data: MockOracleData) -> ProgramResult {
+    if !cfg!(feature = "testing") {
+        return Err(ErrorCode::MockOracleDisabled.into());
+    }
+
+    let mock_oracle = &mut ctx.accounts.mock_oracle;
+    mock_oracle.spot_price = data.spot_price;
+    mock_oracle.iv = data.iv;
+    mock_oracle.timestamp = data.timestamp;
+
+    msg!(
+        "mock oracle updated, spot_price: {}, iv: {}, timestamp: {}",
+        data.spot_price,
+        data.iv,
+        data.timestamp
+    );
+    Ok(())
+}
@@ -5,8 +5,11 @@ pub mod init_optifi_exchange;
 pub mod liquidations;
 pub mod margin;
 pub mod market_maker;
+pub mod mock_oracle;
 pub mod optifi_market;
+pub mod oracle_config;
 pub mod order;
+pub mod referral;
 pub mod user;
 
 pub use amm::*;
@@ -16,6 +19,9 @@ pub use init_optifi_exchange::*;
 pub use liquidations::*;
 pub use margin::*;
 pub use market_maker::*;
+pub use mock_oracle::*;
 pub use optifi_market::*;
+pub use oracle_config::*;
 pub use order::*;
+pub use referral::*;
 pub use user::*;
@@ -1,11 +1,14 @@
 use crate::errors::{Error, ErrorCode};
 use crate::financial::{
-    get_asset_to_usdc_spot, instruments::InstrumentType, verify_switchboard_account, Asset, Chain,
-    OracleDataType,
+    get_asset_to_usdc_spot, get_oracle_timestamp, get_usdc_to_usd_spot,
+    instruments::InstrumentType, is_oracle_stale, is_usdc_depegged, verify_switchboard_account,
+    Asset, Chain, OracleDataType,
 };
 use crate::instructions::order::{
     instrument_spl_token_utils::burn_instrument_token_for_user,
-    serum_utils::{serum_prune_orders_for_user, serum_settle_funds_for_user},
+    serum_utils::{
+        serum_open_orders_balances, serum_prune_orders_for_user, serum_settle_funds_for_user,
+    },
 };
 use crate::state::{Exchange, OptifiMarket, UserAccount};
 use crate::utils::{
@@ -128,6 +131,24 @@ pub fn record_pnl_for_one_user(ctx: Context<RecordPnLForOneUser>) -> ProgramResu
         return Err(ErrorCode::IncorrectOracleAccount.into());
     }
 
+    // the instrument is settled at fresh prices only, and not while usdc is depegged,
+    // as the margin stress is
+    let now = ctx.accounts.clock.unix_timestamp as u64;
+    if is_oracle_stale(get_oracle_timestamp(asset_oracle_feed), now)
+        || is_oracle_stale(get_oracle_timestamp(usdc_oracle_feed), now)
+    {
+        return Err(ErrorCode::StaleOracle.into());
+    }
+    if is_usdc_depegged(get_usdc_to_usd_spot(usdc_oracle_feed)) {
+        return Err(ErrorCode::UsdcDepegged.into());
+    }
+
+    // the maker fills since the last operation are recorded from the balances before the prune
+    let balances_before_prune = serum_open_orders_balances(
+        serum_market,
+        user_serum_open_orders,
+        &user_account.to_account_info(),
+    )?;
     serum_prune_orders_for_user(
         dex_program,
         serum_market,
@@ -166,6 +187,16 @@ pub fn record_pnl_for_one_user(ctx: Context<RecordPnLForOneUser>) -> ProgramResu
         &ctx.program_id,
         // &optifi_exchange.key(),
     )?;
+    let balances_after_settlement = serum_open_orders_balances(
+        serum_market,
+        user_serum_open_orders,
+        &user_account.to_account_info(),
+    )?;
+    user_account.record_open_orders_fills(
+        optifi_market.instrument,
+        balances_before_prune,
+        balances_after_settlement,
+    );
 
     let long_amount = amount(user_instrument_long_token_vault).unwrap();
     let short_amount = amount(user_instrument_short_token_vault).unwrap();
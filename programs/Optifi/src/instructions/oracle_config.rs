@@ -0,0 +1,54 @@
+use crate::constants::MAX_IV_TERM_ORACLES;
+use crate::errors::ErrorCode;
+use crate::financial::Asset;
+use crate::state::{Exchange, IvTermOracle};
+use anchor_lang::prelude::*;
+use std::convert::TryFrom;
+
+#[derive(Accounts)]
+pub struct UpdateIvTermOracles<'info> {
+    /// optifi exchange account
+    #[account(mut, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// optifi exchange's authority
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Set the iv term structure oracles of an asset,
+/// the tenors must be sorted in ascending order
+pub fn set_iv_term_oracles_handler(
+    ctx: Context<UpdateIvTermOracles>,
+    asset: u8,
+    iv_term_oracles: Vec<IvTermOracle>,
+) -> ProgramResult {
+    let asset = Asset::try_from(asset).map_err(|_| ErrorCode::WrongAsset)?;
+    if asset == Asset::USDC {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
+    if iv_term_oracles.len() > MAX_IV_TERM_ORACLES
+        || iv_term_oracles.iter().any(|term_oracle| term_oracle.tenor == 0)
+        || iv_term_oracles
+            .windows(2)
+            .any(|pair| pair[0].tenor >= pair[1].tenor)
+    {
+        return Err(ErrorCode::InvalidIvTermStructure.into());
+    }
+
+    let optifi_exchange = &mut ctx.accounts.optifi_exchange;
+    let oracle_data = optifi_exchange
+        .oracle
+        .iter_mut()
+        .find(|o| o.asset == asset)
+        .ok_or(ErrorCode::WrongAsset)?;
+
+    msg!(
+        "set {} iv term structure oracles for {:?}",
+        iv_term_oracles.len(),
+        asset
+    );
+    oracle_data.iv_term_oracles = iv_term_oracles;
+
+    Ok(())
+}
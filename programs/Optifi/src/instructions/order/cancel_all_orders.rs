@@ -0,0 +1,355 @@
+use crate::errors::ErrorCode;
+use crate::instructions::user::refresh_user_margin;
+use crate::instrument_spl_token_utils::burn_instrument_token_for_user;
+use crate::serum_utils::{
+    iter_filled_slots, serum_cancel_order, serum_open_bid_quantity, serum_open_orders_balances,
+    serum_settle_funds_for_user,
+};
+use crate::state::{MarginStressAccount, OptifiMarket, UserAccount};
+use crate::utils::PREFIX_USER_ACCOUNT;
+use crate::Exchange;
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, accessor};
+use serum_dex::critbit::SlabView;
+use serum_dex::matching::Side;
+use serum_dex::state::Market;
+
+use super::{close_order_metadata_accounts, split_order_metadata_accounts, CancelOrderContext};
+
+/// number of accounts of each market in ctx.remaining_accounts of cancel_all_orders_batch
+pub const CANCEL_MARKET_ACCOUNTS: usize = 13;
+
+/// accounts of a market whose orders are cancelled
+pub struct CancelMarketAccounts<'a, 'info> {
+    pub instrument: Pubkey,
+    pub serum_market: &'a AccountInfo<'info>,
+    pub open_orders: &'a AccountInfo<'info>,
+    pub event_queue: &'a AccountInfo<'info>,
+    pub bids: &'a AccountInfo<'info>,
+    pub asks: &'a AccountInfo<'info>,
+    pub coin_mint: &'a AccountInfo<'info>,
+    pub coin_vault: &'a AccountInfo<'info>,
+    pub pc_vault: &'a AccountInfo<'info>,
+    pub vault_signer: &'a AccountInfo<'info>,
+    pub user_instrument_long_token_vault: &'a AccountInfo<'info>,
+    pub user_instrument_short_token_vault: &'a AccountInfo<'info>,
+    pub instrument_short_spl_token_mint: &'a AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAllOrdersContext<'info> {
+    /// optifi_exchange account
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// margin stress account of the markets' asset
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+    /// the user's wallet, which receives the rent of the closed order metadata accounts
+    #[account(mut, signer)]
+    pub user: AccountInfo<'info>,
+    /// user's optifi account
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key() && user_account.owner == user.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    /// user's margin account which is controlled by a pda
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
+    pub user_margin_account: AccountInfo<'info>,
+    pub serum_dex_program_id: AccountInfo<'info>,
+    #[account(address = token::ID)]
+    pub token_program: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the accounts of each market into ctx.remaining_accounts,
+    // CANCEL_MARKET_ACCOUNTS accounts for each market:
+    //   optifi_market, serum_market, open_orders, event_queue, bids, asks,
+    //   coin_mint (instrument long spl token), coin_vault, pc_vault,
+    //   vault_signer, user_instrument_long_token_vault,
+    //   user_instrument_short_token_vault, instrument_short_spl_token_mint
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets after the accounts of the markets
+    // the order metadata accounts of the cancelled good-til-time orders
+    // can be passed after the accounts of the markets as well, they are closed
+    // ====================================================================
+}
+
+/// Cancel all the user's orders on the market, settle the funds
+/// and burn the long and short tokens which were minted for the cancelled asks
+pub fn cancel_all_orders_on_market<'info>(
+    program_id: &Pubkey,
+    exchange_key: &Pubkey,
+    dex_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    user_account: &mut UserAccount,
+    user_account_info: &AccountInfo<'info>,
+    user_margin_account: &AccountInfo<'info>,
+    market: &CancelMarketAccounts<'_, 'info>,
+) -> ProgramResult {
+    let user_key = user_account.owner;
+    let user_bump = user_account.bump;
+    let signer_seeds: &[&[u8]] = &[
+        PREFIX_USER_ACCOUNT.as_bytes(),
+        exchange_key.as_ref(),
+        user_key.as_ref(),
+        &[user_bump],
+    ];
+
+    // the user's orders, and the coin quantity of the asks
+    let orders = {
+        let serum_market = Market::load(market.serum_market, dex_program.key)?;
+        let coin_lot_size = serum_market.coin_lot_size;
+        let open_orders = serum_market.load_orders_mut(
+            market.open_orders,
+            Some(user_account_info),
+            dex_program.key,
+            None,
+            None,
+        )?;
+        let asks = serum_market.load_asks_mut(market.asks)?;
+        iter_filled_slots(*open_orders)
+            .filter_map(|slot| {
+                let side = open_orders.slot_side(slot)?;
+                let order_id = open_orders.orders[slot as usize];
+                let ask_qty = match side {
+                    Side::Ask => asks
+                        .find_by_key(order_id)
+                        .and_then(|handle| asks.get(handle))
+                        .and_then(|node| node.as_leaf())
+                        .map_or(0, |node| node.quantity() * coin_lot_size),
+                    Side::Bid => 0,
+                };
+                Some((side, order_id, ask_qty))
+            })
+            .collect::<Vec<(Side, u128, u64)>>()
+    };
+
+    // the maker fills since the last operation are recorded from the balances before the cancel
+    let balances_before_cancel =
+        serum_open_orders_balances(market.serum_market, market.open_orders, user_account_info)?;
+    msg!("cancelling {} orders", orders.len());
+    for &(side, order_id, _) in orders.iter() {
+        serum_cancel_order(
+            signer_seeds,
+            dex_program,
+            market.serum_market,
+            market.bids,
+            market.asks,
+            market.open_orders,
+            user_account_info,
+            market.event_queue,
+            side,
+            order_id,
+        )?;
+    }
+
+    serum_settle_funds_for_user(
+        signer_seeds,
+        dex_program,
+        market.serum_market,
+        token_program,
+        market.open_orders,
+        user_account_info,
+        market.coin_vault,
+        market.user_instrument_long_token_vault,
+        market.pc_vault,
+        user_margin_account,
+        market.vault_signer,
+        program_id,
+    )?;
+    let balances_after_settlement =
+        serum_open_orders_balances(market.serum_market, market.open_orders, user_account_info)?;
+
+    // burn the same amount of both instrument long and short tokens for the cancelled asks
+    let amount_to_burn = orders
+        .iter()
+        .map(|&(_, _, ask_qty)| ask_qty)
+        .sum::<u64>()
+        .min(accessor::amount(market.user_instrument_long_token_vault)?)
+        .min(accessor::amount(market.user_instrument_short_token_vault)?);
+    if amount_to_burn > 0 {
+        burn_instrument_token_for_user(
+            market.coin_mint,
+            market.user_instrument_long_token_vault,
+            user_key,
+            user_account_info,
+            user_bump,
+            amount_to_burn,
+            token_program,
+            exchange_key,
+        )?;
+        burn_instrument_token_for_user(
+            market.instrument_short_spl_token_mint,
+            market.user_instrument_short_token_vault,
+            user_key,
+            user_account_info,
+            user_bump,
+            amount_to_burn,
+            token_program,
+            exchange_key,
+        )?;
+    }
+
+    let open_bid_qty = serum_open_bid_quantity(
+        market.serum_market,
+        market.open_orders,
+        user_account_info,
+        market.bids,
+    )?;
+    let long_amount = accessor::amount(market.user_instrument_long_token_vault)?;
+    let short_amount = accessor::amount(market.user_instrument_short_token_vault)?;
+
+    user_account.record_open_orders_fills(
+        market.instrument,
+        balances_before_cancel,
+        balances_after_settlement,
+    );
+    user_account.update_long_position(market.instrument, long_amount);
+    user_account.update_short_position(market.instrument, short_amount);
+    user_account.update_open_bid_quantity(market.instrument, open_bid_qty);
+
+    Ok(())
+}
+
+/// Cancel all the user's orders on one optifi market
+pub fn handle_cancel_all(ctx: Context<CancelOrderContext>) -> ProgramResult {
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let optifi_market = &ctx.accounts.optifi_market;
+    let user_account = &mut ctx.accounts.user_account;
+
+    if user_account.is_in_liquidation {
+        return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
+    }
+    if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+        != Some(ctx.accounts.margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
+    let (order_metadata_accounts, stress_accounts) =
+        split_order_metadata_accounts(ctx.program_id, ctx.remaining_accounts);
+    let user_account_info = user_account.to_account_info();
+    cancel_all_orders_on_market(
+        ctx.program_id,
+        &optifi_exchange.key(),
+        &ctx.accounts.serum_dex_program_id,
+        &ctx.accounts.token_program,
+        user_account,
+        &user_account_info,
+        &ctx.accounts.user_margin_account,
+        &CancelMarketAccounts {
+            instrument: optifi_market.instrument,
+            serum_market: &ctx.accounts.serum_market,
+            open_orders: &ctx.accounts.open_orders,
+            event_queue: &ctx.accounts.event_queue,
+            bids: &ctx.accounts.bids,
+            asks: &ctx.accounts.asks,
+            coin_mint: &ctx.accounts.coin_mint,
+            coin_vault: &ctx.accounts.coin_vault,
+            pc_vault: &ctx.accounts.pc_vault,
+            vault_signer: &ctx.accounts.vault_signer,
+            user_instrument_long_token_vault: &ctx.accounts.user_instrument_long_token_vault,
+            user_instrument_short_token_vault: &ctx.accounts.user_instrument_short_token_vault,
+            instrument_short_spl_token_mint: &ctx.accounts.instrument_short_spl_token_mint,
+        },
+    )?;
+    close_order_metadata_accounts(
+        &optifi_market.key(),
+        &ctx.accounts.serum_market,
+        &ctx.accounts.open_orders,
+        &user_account_info,
+        &ctx.accounts.user,
+        &order_metadata_accounts,
+    )?;
+
+    // release the margin reserved for the cancelled orders
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        &ctx.accounts.user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        &stress_accounts,
+    )
+}
+
+/// Cancel all the user's orders on several optifi markets
+pub fn handle_cancel_all_batch(ctx: Context<CancelAllOrdersContext>, markets: u8) -> ProgramResult {
+    let markets_accounts_len = markets as usize * CANCEL_MARKET_ACCOUNTS;
+    if markets == 0 || ctx.remaining_accounts.len() < markets_accounts_len {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+    let (markets_accounts, other_accounts) = ctx.remaining_accounts.split_at(markets_accounts_len);
+    let (order_metadata_accounts, stress_accounts) =
+        split_order_metadata_accounts(ctx.program_id, other_accounts);
+
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let user_account = &mut ctx.accounts.user_account;
+    if user_account.is_in_liquidation {
+        return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
+    }
+    let user_account_info = user_account.to_account_info();
+
+    for accounts in markets_accounts.chunks(CANCEL_MARKET_ACCOUNTS) {
+        let optifi_market_info = &accounts[0];
+        if optifi_market_info.owner != ctx.program_id
+            || !optifi_exchange
+                .markets
+                .iter()
+                .any(|m| m.optifi_market_pubkey == optifi_market_info.key())
+        {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        let optifi_market =
+            OptifiMarket::try_deserialize(&mut &optifi_market_info.data.borrow()[..])?;
+        if optifi_market.serum_market != accounts[1].key()
+            || optifi_market.instrument_long_spl_token != accounts[6].key()
+            || optifi_market.instrument_short_spl_token != accounts[12].key()
+            || accessor::mint(&accounts[10])? != optifi_market.instrument_long_spl_token
+            || accessor::authority(&accounts[10])? != user_account_info.key()
+            || accessor::mint(&accounts[11])? != optifi_market.instrument_short_spl_token
+            || accessor::authority(&accounts[11])? != user_account_info.key()
+        {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+
+        cancel_all_orders_on_market(
+            ctx.program_id,
+            &optifi_exchange.key(),
+            &ctx.accounts.serum_dex_program_id,
+            &ctx.accounts.token_program,
+            user_account,
+            &user_account_info,
+            &ctx.accounts.user_margin_account,
+            &CancelMarketAccounts {
+                instrument: optifi_market.instrument,
+                serum_market: &accounts[1],
+                open_orders: &accounts[2],
+                event_queue: &accounts[3],
+                bids: &accounts[4],
+                asks: &accounts[5],
+                coin_mint: &accounts[6],
+                coin_vault: &accounts[7],
+                pc_vault: &accounts[8],
+                vault_signer: &accounts[9],
+                user_instrument_long_token_vault: &accounts[10],
+                user_instrument_short_token_vault: &accounts[11],
+                instrument_short_spl_token_mint: &accounts[12],
+            },
+        )?;
+        close_order_metadata_accounts(
+            &optifi_market_info.key(),
+            &accounts[1],
+            &accounts[2],
+            &user_account_info,
+            &ctx.accounts.user,
+            &order_metadata_accounts,
+        )?;
+    }
+
+    // release the margin reserved for the cancelled orders, the margin of other assets
+    // is recalculated the next time they are traded
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        &ctx.accounts.user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        &stress_accounts,
+    )
+}
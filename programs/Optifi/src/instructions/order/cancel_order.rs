@@ -1,50 +1,60 @@
 use crate::errors::ErrorCode;
-use crate::instructions::order::serum_utils::serum_cancel_order_with_client_order_id;
 use crate::instrument_spl_token_utils::burn_instrument_token_for_user;
-use crate::serum_utils::{serum_cancel_order, serum_settle_funds_for_user};
+use crate::serum_utils::{
+    iter_filled_slots, serum_cancel_order, serum_open_bid_quantity, serum_open_orders_balances,
+    serum_settle_funds_for_user,
+};
 use crate::utils::PREFIX_USER_ACCOUNT;
 
-use crate::state::OptifiMarket;
+use crate::instructions::user::refresh_user_margin;
 use crate::state::UserAccount;
+use crate::state::{MarginStressAccount, OptifiMarket};
 use crate::{Exchange, OrderSide};
 use anchor_lang::prelude::*;
 use anchor_spl::token;
-use anchor_spl::token::accessor::amount;
+use anchor_spl::token::accessor::{self, amount};
 use serum_dex::critbit::SlabView;
 use serum_dex::error::DexErrorCode;
 use serum_dex::matching::Side;
 use serum_dex::state::{Market, OpenOrders};
 use std::num::NonZeroU64;
-use std::ops::DerefMut;
+
+use super::{close_order_metadata_accounts, split_order_metadata_accounts, CancelMarketAccounts};
 
 /// Accounts used to place orders on the DEX
 #[derive(Accounts, Clone)]
 pub struct CancelOrderContext<'info> {
     /// optifi_exchange account
     pub optifi_exchange: ProgramAccount<'info, Exchange>,
-    /// the user's wallet
-    #[account(signer)]
+    /// margin stress account of the instrument's asset
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+    /// the user's wallet, which receives the rent of the closed order metadata accounts
+    #[account(mut, signer)]
     pub user: AccountInfo<'info>,
     /// user's optifi account
-    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key())]
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key()
+        && user_account.owner == user.key() @ ErrorCode::UnauthorizedAccount)]
     pub user_account: ProgramAccount<'info, UserAccount>,
     /// user's margin account which is controlled by a pda
-    #[account(mut)]
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
     pub user_margin_account: AccountInfo<'info>,
     /// user's instrument long spl token account which is controlled by a the user's user account(pda)
     /// it stands for how many contracts the user sold for the instrument
     /// and it should be the same as order_payer_token_account if the order is ask order
-    #[account(mut)]
+    #[account(mut, constraint = accessor::mint(&user_instrument_long_token_vault)? == optifi_market.instrument_long_spl_token
+        && accessor::authority(&user_instrument_long_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_long_token_vault: AccountInfo<'info>,
     /// user's instrument short spl token account which is controlled by a the user's user account(pda)
     /// it stands for how many contracts the user bought for the instrument
-    #[account(mut)]
+    #[account(mut, constraint = accessor::mint(&user_instrument_short_token_vault)? == optifi_market.instrument_short_spl_token
+        && accessor::authority(&user_instrument_short_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_short_token_vault: AccountInfo<'info>,
     /// optifi market that binds an instrument with a serum market(orderbook)
     /// it's also the mint authority of the instrument spl token
     pub optifi_market: ProgramAccount<'info, OptifiMarket>,
     /// the serum market(orderbook)
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.serum_market == serum_market.key())]
     pub serum_market: AccountInfo<'info>,
     /// the user's open orders account
     #[account(mut)]
@@ -59,7 +69,7 @@ pub struct CancelOrderContext<'info> {
     #[account(mut)]
     pub asks: AccountInfo<'info>,
     /// The token mint address of "base" currency, aka the instrument long spl token
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_long_spl_token == coin_mint.key())]
     pub coin_mint: AccountInfo<'info>,
     /// The vault for the "base" currency
     #[account(mut)]
@@ -78,7 +88,7 @@ pub struct CancelOrderContext<'info> {
     #[account(constraint = usdc_central_pool.key() == optifi_exchange.usdc_central_pool)]
     pub usdc_central_pool: AccountInfo<'info>,
     /// the instrument short spl token
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_short_spl_token == instrument_short_spl_token_mint.key())]
     pub instrument_short_spl_token_mint: AccountInfo<'info>,
     pub serum_dex_program_id: AccountInfo<'info>,
     #[account(address = token::ID)]
@@ -91,154 +101,76 @@ pub struct CancelOrderContext<'info> {
     // pub iv_feed: AccountInfo<'info>,
     // // Clock to get the timestamp
     // pub clock: Sysvar<'info, Clock>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // the order metadata accounts of the cancelled good-til-time orders
+    // can be passed into ctx.remaining_accounts as well, they are closed
+    // ====================================================================
 }
 
 pub fn handle(ctx: Context<CancelOrderContext>, side: OrderSide, order_id: u128) -> ProgramResult {
     let optifi_exchange = &ctx.accounts.optifi_exchange;
-    let user = &ctx.accounts.user;
-    let user_account = &mut ctx.accounts.user_account;
     let optifi_market = &ctx.accounts.optifi_market;
-    let serum_market = &ctx.accounts.serum_market;
-    let coin_mint = &ctx.accounts.coin_mint;
-    let open_orders = &ctx.accounts.open_orders;
-    let event_queue = &ctx.accounts.event_queue;
-    let market_bids = &ctx.accounts.bids;
-    let market_asks = &ctx.accounts.asks;
-    // let order_payer = &ctx.accounts.order_payer_token_account;
-
-    // let open_orders_authority = &ctx.accounts.open_orders_owner;
-    let token_program = &ctx.accounts.token_program;
-    let dex_program = &ctx.accounts.serum_dex_program_id;
-    let coin_vault = &ctx.accounts.coin_vault;
-    let pc_vault = &ctx.accounts.pc_vault;
-    let user_margin_account = &ctx.accounts.user_margin_account;
-
-    // order_payer account should be usdc vault(margin account) if order is Bid
-    // and long token vault for
-    let mut order_payer = user_margin_account;
-
-    let user_instrument_long_token_vault = &ctx.accounts.user_instrument_long_token_vault;
-    let user_instrument_short_token_vault = &ctx.accounts.user_instrument_short_token_vault;
-    let instrument_short_spl_token_mint = &ctx.accounts.instrument_short_spl_token_mint;
+    let user_account = &mut ctx.accounts.user_account;
 
     if user_account.is_in_liquidation {
         return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
     }
 
-    // 0 is bid, 1 is ask - for the purpose of this, anything non-zero,
-    // will be interpreted as ask
-    let serum_side: Side;
-    match side {
-        OrderSide::Bid => serum_side = Side::Bid,
-        OrderSide::Ask => serum_side = Side::Ask,
+    if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+        != Some(ctx.accounts.margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
     }
 
-    // get the order amount
-    let mut order_amount: u64 = 0;
-    let mut lot_size: u64 = 0;
-
-    if serum_side == Side::Ask {
-        let program_id = dex_program.key;
-        let market = Market::load(serum_market, program_id)?;
-        lot_size = market.coin_lot_size;
-
-        // let mut bids = market.load_bids_mut(market_bids)?;
-        let mut asks = market.load_asks_mut(market_asks)?;
-
-        let key = asks
-            .find_by_key(order_id)
-            .ok_or(DexErrorCode::OrderNotFound)
-            .unwrap();
-
-        let node = asks.deref_mut().get(key).unwrap().as_leaf().unwrap();
-
-        order_amount = node.quantity();
-
-        order_payer = &ctx.accounts.user_instrument_long_token_vault;
-    }
-
-    let exchange_key = optifi_exchange.key();
-
-    let signer_seeds = &[
-        PREFIX_USER_ACCOUNT.as_bytes(),
-        exchange_key.as_ref(),
-        user_account.owner.as_ref(),
-        &[user_account.bump],
-    ];
-    msg!("cancelling the previous order");
-    serum_cancel_order(
-        signer_seeds,
-        dex_program,
-        serum_market,
-        market_bids,
-        market_asks,
-        open_orders,
-        &user_account.to_account_info(),
-        event_queue,
-        serum_side,
+    let (order_metadata_accounts, stress_accounts) =
+        split_order_metadata_accounts(ctx.program_id, ctx.remaining_accounts);
+    let user_account_info = user_account.to_account_info();
+    cancel_order_on_market(
+        ctx.program_id,
+        &optifi_exchange.key(),
+        &ctx.accounts.serum_dex_program_id,
+        &ctx.accounts.token_program,
+        user_account,
+        &user_account_info,
+        &ctx.accounts.user_margin_account,
+        &CancelMarketAccounts {
+            instrument: optifi_market.instrument,
+            serum_market: &ctx.accounts.serum_market,
+            open_orders: &ctx.accounts.open_orders,
+            event_queue: &ctx.accounts.event_queue,
+            bids: &ctx.accounts.bids,
+            asks: &ctx.accounts.asks,
+            coin_mint: &ctx.accounts.coin_mint,
+            coin_vault: &ctx.accounts.coin_vault,
+            pc_vault: &ctx.accounts.pc_vault,
+            vault_signer: &ctx.accounts.vault_signer,
+            user_instrument_long_token_vault: &ctx.accounts.user_instrument_long_token_vault,
+            user_instrument_short_token_vault: &ctx.accounts.user_instrument_short_token_vault,
+            instrument_short_spl_token_mint: &ctx.accounts.instrument_short_spl_token_mint,
+        },
+        side,
         order_id,
     )?;
-
-    // let instrument_token_mint_authority_pda = &ctx.accounts.instrument_token_mint_authority_pda;
-    let vault_signer = &ctx.accounts.vault_signer;
-    // settle funds - get base tokens back
-    serum_settle_funds_for_user(
-        // user.key,
-        signer_seeds,
-        dex_program,
-        serum_market,
-        token_program,
-        open_orders,
-        &user_account.to_account_info(),
-        // user_account.bump,
-        coin_vault,
-        user_instrument_long_token_vault,
-        pc_vault,
-        user_margin_account,
-        vault_signer,
-        &ctx.program_id,
-        // optifi_exchange.key,
+    close_order_metadata_accounts(
+        &optifi_market.key(),
+        &ctx.accounts.serum_market,
+        &ctx.accounts.open_orders,
+        &user_account_info,
+        &ctx.accounts.user,
+        &order_metadata_accounts,
     )?;
 
-    // burn the same amount of both instrument long and short tokens if ask side
-    if serum_side == Side::Ask {
-        let amount_to_burn = order_amount
-            .checked_div(lot_size)
-            .ok_or(ErrorCode::NumericalOverflowError)? as u64;
-        // burn the long tokens
-        burn_instrument_token_for_user(
-            coin_mint,
-            order_payer, // order_payer is the same as user_instrument_long_token_vault when the order is ask order
-            user.key(),
-            &user_account.to_account_info(),
-            user_account.bump,
-            amount_to_burn,
-            token_program,
-            &optifi_exchange.key(),
-        )?;
-
-        // burn the short tokens
-        burn_instrument_token_for_user(
-            instrument_short_spl_token_mint,
-            user_instrument_short_token_vault,
-            user.key(),
-            &user_account.to_account_info(),
-            user_account.bump,
-            amount_to_burn,
-            token_program,
-            &optifi_exchange.key(),
-        )?;
-
-        msg!("successfully burn spl tokens to the seller spl token account");
-    }
-
-    let long_amount = amount(user_instrument_long_token_vault).unwrap();
-    let short_amount = amount(user_instrument_short_token_vault).unwrap();
-
-    user_account.update_long_position(optifi_market.instrument, long_amount);
-    user_account.update_short_position(optifi_market.instrument, short_amount);
-
-    Ok(())
+    // release the margin reserved for the cancelled order
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        &ctx.accounts.user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        &stress_accounts,
+    )
 }
 
 pub fn handle2(
@@ -247,168 +179,239 @@ pub fn handle2(
     client_order_id: u64,
 ) -> ProgramResult {
     let optifi_exchange = &ctx.accounts.optifi_exchange;
-    let user = &ctx.accounts.user;
-    let user_account = &mut ctx.accounts.user_account;
     let optifi_market = &ctx.accounts.optifi_market;
-    let serum_market = &ctx.accounts.serum_market;
-    let coin_mint = &ctx.accounts.coin_mint;
-    let open_orders = &ctx.accounts.open_orders;
-    let event_queue = &ctx.accounts.event_queue;
-    let market_bids = &ctx.accounts.bids;
-    let market_asks = &ctx.accounts.asks;
-    // let order_payer = &ctx.accounts.order_payer_token_account;
-
-    // let open_orders_authority = &ctx.accounts.open_orders_owner;
-    let token_program = &ctx.accounts.token_program;
-    let dex_program = &ctx.accounts.serum_dex_program_id;
-    let coin_vault = &ctx.accounts.coin_vault;
-    let pc_vault = &ctx.accounts.pc_vault;
-    let user_margin_account = &ctx.accounts.user_margin_account;
-
-    // order_payer account should be usdc vault(margin account) if order is Bid
-    // and long token vault for
-    let mut order_payer = user_margin_account;
-
-    let user_instrument_long_token_vault = &ctx.accounts.user_instrument_long_token_vault;
-    let user_instrument_short_token_vault = &ctx.accounts.user_instrument_short_token_vault;
-    let instrument_short_spl_token_mint = &ctx.accounts.instrument_short_spl_token_mint;
+    let user_account = &mut ctx.accounts.user_account;
 
     if user_account.is_in_liquidation {
         return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
     }
 
-    // 0 is bid, 1 is ask - for the purpose of this, anything non-zero,
-    // will be interpreted as ask
-    let serum_side: Side;
-    match side {
-        OrderSide::Bid => serum_side = Side::Bid,
-        OrderSide::Ask => serum_side = Side::Ask,
+    if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+        != Some(ctx.accounts.margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
     }
 
-    // get the order amount
-    let mut order_amount: u64 = 0;
-    let mut lot_size: u64 = 0;
-
-    if serum_side == Side::Ask {
-        let program_id = dex_program.key;
-        let market = Market::load(serum_market, program_id)?;
-        lot_size = market.coin_lot_size;
+    let (order_metadata_accounts, stress_accounts) =
+        split_order_metadata_accounts(ctx.program_id, ctx.remaining_accounts);
+    let user_account_info = user_account.to_account_info();
+    cancel_order_by_client_order_id_on_market(
+        ctx.program_id,
+        &optifi_exchange.key(),
+        &ctx.accounts.serum_dex_program_id,
+        &ctx.accounts.token_program,
+        user_account,
+        &user_account_info,
+        &ctx.accounts.user_margin_account,
+        &CancelMarketAccounts {
+            instrument: optifi_market.instrument,
+            serum_market: &ctx.accounts.serum_market,
+            open_orders: &ctx.accounts.open_orders,
+            event_queue: &ctx.accounts.event_queue,
+            bids: &ctx.accounts.bids,
+            asks: &ctx.accounts.asks,
+            coin_mint: &ctx.accounts.coin_mint,
+            coin_vault: &ctx.accounts.coin_vault,
+            pc_vault: &ctx.accounts.pc_vault,
+            vault_signer: &ctx.accounts.vault_signer,
+            user_instrument_long_token_vault: &ctx.accounts.user_instrument_long_token_vault,
+            user_instrument_short_token_vault: &ctx.accounts.user_instrument_short_token_vault,
+            instrument_short_spl_token_mint: &ctx.accounts.instrument_short_spl_token_mint,
+        },
+        side,
+        client_order_id,
+    )?;
+    close_order_metadata_accounts(
+        &optifi_market.key(),
+        &ctx.accounts.serum_market,
+        &ctx.accounts.open_orders,
+        &user_account_info,
+        &ctx.accounts.user,
+        &order_metadata_accounts,
+    )?;
 
-        // let mut bids = market.load_bids_mut(market_bids)?;
-        let mut asks = market.load_asks_mut(market_asks)?;
+    // release the margin reserved for the cancelled order
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        &ctx.accounts.user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        &stress_accounts,
+    )
+}
 
-        let open_orders = market.load_orders_mut(
-            open_orders,
-            Some(&user_account.to_account_info()),
-            program_id,
+/// Cancel the user's order of `client_order_id` on the market, settle the funds
+/// and burn the long and short tokens which were minted for the order if it's an ask
+pub fn cancel_order_by_client_order_id_on_market<'info>(
+    program_id: &Pubkey,
+    exchange_key: &Pubkey,
+    dex_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    user_account: &mut UserAccount,
+    user_account_info: &AccountInfo<'info>,
+    user_margin_account: &AccountInfo<'info>,
+    market: &CancelMarketAccounts<'_, 'info>,
+    side: OrderSide,
+    client_order_id: u64,
+) -> ProgramResult {
+    let order_id = {
+        let serum_market = Market::load(market.serum_market, dex_program.key)?;
+        let open_orders = serum_market.load_orders_mut(
+            market.open_orders,
+            Some(user_account_info),
+            dex_program.key,
             None,
             None,
         )?;
+        orders_with_client_ids(*open_orders)
+            .find(|(id, _, _)| id.get() == client_order_id)
+            .map(|(_, order_id, _)| order_id)
+            .ok_or(DexErrorCode::OrderNotFound)?
+    };
+    cancel_order_on_market(
+        program_id,
+        exchange_key,
+        dex_program,
+        token_program,
+        user_account,
+        user_account_info,
+        user_margin_account,
+        market,
+        side,
+        order_id,
+    )
+}
 
-        let slot = open_orders
-            .client_order_ids
-            .iter()
-            .position(|&r| r == client_order_id)
-            .unwrap();
-        let order_id = open_orders.orders[slot];
-
-        let key = asks
-            .find_by_key(order_id)
-            .ok_or(DexErrorCode::OrderNotFound)
-            .unwrap();
-        let node = asks.deref_mut().get(key).unwrap().as_leaf().unwrap();
-
-        order_amount = node.quantity();
-
-        order_payer = &ctx.accounts.user_instrument_long_token_vault;
-    }
-
-    let exchange_key = optifi_exchange.key();
-
-    let signer_seeds = &[
+/// Cancel the user's order of the serum `order_id` on the market, settle the funds
+/// and burn the long and short tokens which were minted for the order if it's an ask
+pub fn cancel_order_on_market<'info>(
+    program_id: &Pubkey,
+    exchange_key: &Pubkey,
+    dex_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    user_account: &mut UserAccount,
+    user_account_info: &AccountInfo<'info>,
+    user_margin_account: &AccountInfo<'info>,
+    market: &CancelMarketAccounts<'_, 'info>,
+    side: OrderSide,
+    order_id: u128,
+) -> ProgramResult {
+    let user_key = user_account.owner;
+    let user_bump = user_account.bump;
+    let signer_seeds: &[&[u8]] = &[
         PREFIX_USER_ACCOUNT.as_bytes(),
         exchange_key.as_ref(),
-        user_account.owner.as_ref(),
-        &[user_account.bump],
+        user_key.as_ref(),
+        &[user_bump],
     ];
+
+    let serum_side = match side {
+        OrderSide::Bid => Side::Bid,
+        OrderSide::Ask => Side::Ask,
+    };
+
+    // the coin quantity of the order if it's an ask, a filled order isn't on the orderbook
+    let ask_qty = match serum_side {
+        Side::Ask => {
+            let serum_market = Market::load(market.serum_market, dex_program.key)?;
+            let asks = serum_market.load_asks_mut(market.asks)?;
+            asks.find_by_key(order_id)
+                .and_then(|handle| asks.get(handle))
+                .and_then(|node| node.as_leaf())
+                .map(|node| node.quantity() * serum_market.coin_lot_size)
+                .ok_or(DexErrorCode::OrderNotFound)?
+        }
+        Side::Bid => 0,
+    };
+
+    // the maker fills since the last operation are recorded from the balances before the cancel
+    let balances_before_cancel =
+        serum_open_orders_balances(market.serum_market, market.open_orders, user_account_info)?;
     msg!("cancelling the previous order");
-    serum_cancel_order_with_client_order_id(
+    serum_cancel_order(
         signer_seeds,
         dex_program,
-        serum_market,
-        market_bids,
-        market_asks,
-        open_orders,
-        &user_account.to_account_info(),
-        event_queue,
+        market.serum_market,
+        market.bids,
+        market.asks,
+        market.open_orders,
+        user_account_info,
+        market.event_queue,
         serum_side,
-        client_order_id,
+        order_id,
     )?;
 
-    // let instrument_token_mint_authority_pda = &ctx.accounts.instrument_token_mint_authority_pda;
-    let vault_signer = &ctx.accounts.vault_signer;
     // settle funds - get base tokens back
     serum_settle_funds_for_user(
-        // user.key,
         signer_seeds,
         dex_program,
-        serum_market,
+        market.serum_market,
         token_program,
-        open_orders,
-        &user_account.to_account_info(),
-        // user_account.bump,
-        coin_vault,
-        user_instrument_long_token_vault,
-        pc_vault,
+        market.open_orders,
+        user_account_info,
+        market.coin_vault,
+        market.user_instrument_long_token_vault,
+        market.pc_vault,
         user_margin_account,
-        vault_signer,
-        &ctx.program_id,
-        // optifi_exchange.key,
+        market.vault_signer,
+        program_id,
     )?;
+    let balances_after_settlement =
+        serum_open_orders_balances(market.serum_market, market.open_orders, user_account_info)?;
+    user_account.record_open_orders_fills(
+        market.instrument,
+        balances_before_cancel,
+        balances_after_settlement,
+    );
 
     // burn the same amount of both instrument long and short tokens if ask side
-    if serum_side == Side::Ask {
-        let amount_to_burn = order_amount
-            .checked_div(lot_size)
-            .ok_or(ErrorCode::NumericalOverflowError)? as u64;
-        // burn the long tokens
+    let amount_to_burn = ask_qty
+        .min(amount(market.user_instrument_long_token_vault)?)
+        .min(amount(market.user_instrument_short_token_vault)?);
+    if amount_to_burn > 0 {
         burn_instrument_token_for_user(
-            coin_mint,
-            order_payer, // order_payer is the same as user_instrument_long_token_vault when the order is ask order
-            user.key(),
-            &user_account.to_account_info(),
-            user_account.bump,
+            market.coin_mint,
+            market.user_instrument_long_token_vault,
+            user_key,
+            user_account_info,
+            user_bump,
             amount_to_burn,
             token_program,
-            &optifi_exchange.key(),
+            exchange_key,
         )?;
-
-        // burn the short tokens
         burn_instrument_token_for_user(
-            instrument_short_spl_token_mint,
-            user_instrument_short_token_vault,
-            user.key(),
-            &user_account.to_account_info(),
-            user_account.bump,
+            market.instrument_short_spl_token_mint,
+            market.user_instrument_short_token_vault,
+            user_key,
+            user_account_info,
+            user_bump,
             amount_to_burn,
             token_program,
-            &optifi_exchange.key(),
+            exchange_key,
         )?;
 
         msg!("successfully burn spl tokens to the seller spl token account");
     }
 
-    let long_amount = amount(user_instrument_long_token_vault).unwrap();
-    let short_amount = amount(user_instrument_short_token_vault).unwrap();
+    let long_amount = amount(market.user_instrument_long_token_vault)?;
+    let short_amount = amount(market.user_instrument_short_token_vault)?;
+    user_account.update_long_position(market.instrument, long_amount);
+    user_account.update_short_position(market.instrument, short_amount);
 
-    user_account.update_long_position(optifi_market.instrument, long_amount);
-    user_account.update_short_position(optifi_market.instrument, short_amount);
+    let open_bid_qty = serum_open_bid_quantity(
+        market.serum_market,
+        market.open_orders,
+        user_account_info,
+        market.bids,
+    )?;
+    user_account.update_open_bid_quantity(market.instrument, open_bid_qty);
 
     Ok(())
 }
 
 #[inline]
-fn orders_with_client_ids(
+pub fn orders_with_client_ids(
     open_orders: OpenOrders,
 ) -> impl Iterator<Item = (NonZeroU64, u128, Side)> {
     iter_filled_slots(open_orders).filter_map(move |slot| {
@@ -418,27 +421,3 @@ fn orders_with_client_ids(
         Some((client_order_id, order_id, side))
     })
 }
-
-#[inline]
-fn iter_filled_slots(open_orders: OpenOrders) -> impl Iterator<Item = u8> {
-    struct Iter {
-        bits: u128,
-    }
-    impl Iterator for Iter {
-        type Item = u8;
-        #[inline(always)]
-        fn next(&mut self) -> Option<Self::Item> {
-            if self.bits == 0 {
-                None
-            } else {
-                let next = self.bits.trailing_zeros();
-                let mask = 1u128 << next;
-                self.bits &= !mask;
-                Some(next as u8)
-            }
-        }
-    }
-    Iter {
-        bits: !open_orders.free_slot_bits,
-    }
-}
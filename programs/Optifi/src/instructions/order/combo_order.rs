@@ -0,0 +1,303 @@
+use crate::constants::MAX_COMBO_LEGS;
+use crate::errors::ErrorCode;
+use crate::financial::margin::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
+use crate::instrument_spl_token_utils::mint_instrument_token_for_user;
+use crate::serum_utils::{
+    serum_new_order_with_client_order_id, serum_open_bid_quantity, serum_open_orders_balances,
+    serum_settle_funds_for_user,
+};
+use crate::state::{MarginStressAccount, OptifiMarket, UserAccount};
+use crate::utils::{get_serum_market_auth_pda, PREFIX_SERUM_MARKET_AUTH, PREFIX_USER_ACCOUNT};
+use crate::{Exchange, OrderSide};
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, accessor};
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::Market;
+
+use super::{check_linked_self_trade, is_margin_sufficient, taker_fill};
+
+/// number of accounts of each leg in ctx.remaining_accounts
+pub const COMBO_LEG_ACCOUNTS: usize = 14;
+
+/// one leg of a combo order, its accounts are passed in ctx.remaining_accounts
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ComboLeg {
+    pub side: OrderSide,
+    pub limit: u64,
+    pub max_coin_qty: u64,
+    pub max_pc_qty: u64,
+}
+
+#[derive(Accounts)]
+pub struct ComboOrderContext<'info> {
+    /// optifi_exchange account
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+    /// the user's wallet
+    #[account(signer)]
+    pub user: AccountInfo<'info>,
+    /// user's optifi account
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key() && user_account.owner == user.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    /// user's margin account which is controlled by a pda
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
+    pub user_margin_account: AccountInfo<'info>,
+    /// the mint authoriity of both long and short spl tokens
+    pub instrument_token_mint_authority_pda: AccountInfo<'info>,
+    pub serum_dex_program_id: AccountInfo<'info>,
+    #[account(address = token::ID)]
+    pub token_program: AccountInfo<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the accounts of each leg into ctx.remaining_accounts, in the
+    // order of the legs, COMBO_LEG_ACCOUNTS accounts for each leg:
+    //   optifi_market, serum_market, open_orders, request_queue,
+    //   event_queue, bids, asks, coin_mint (instrument long spl token),
+    //   coin_vault, pc_vault, vault_signer, user_instrument_long_token_vault,
+    //   user_instrument_short_token_vault, instrument_short_spl_token_mint
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets after the accounts of the legs
+    // ====================================================================
+}
+
+/// Place all the legs of a combo order immediate-or-cancel, every leg must be filled in full
+/// or the whole combo fails. The margin is checked once on the resulting portfolio,
+/// so that a spread only needs margin for its combined risk.
+pub fn handle(ctx: Context<ComboOrderContext>, legs: Vec<ComboLeg>) -> ProgramResult {
+    if legs.is_empty() || legs.len() > MAX_COMBO_LEGS {
+        return Err(ErrorCode::InvalidComboOrder.into());
+    }
+    let legs_accounts_len = legs.len() * COMBO_LEG_ACCOUNTS;
+    if ctx.remaining_accounts.len() < legs_accounts_len {
+        return Err(ErrorCode::InvalidComboOrder.into());
+    }
+    let (legs_accounts, stress_accounts) = ctx.remaining_accounts.split_at(legs_accounts_len);
+
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    let user_account = &mut ctx.accounts.user_account;
+    let user_margin_account = &ctx.accounts.user_margin_account;
+    let token_program = &ctx.accounts.token_program;
+    let dex_program = &ctx.accounts.serum_dex_program_id;
+    let rent = &ctx.accounts.rent.to_account_info();
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    margin_stress_account.check_available(
+        now,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
+    if user_account.is_in_liquidation {
+        return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
+    }
+
+    let exchange_key = optifi_exchange.key();
+    let user_key = user_account.owner;
+    let user_bump = user_account.bump;
+    let user_account_info = user_account.to_account_info();
+    let (_market_auth, bump) = get_serum_market_auth_pda(&exchange_key, ctx.program_id);
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            PREFIX_USER_ACCOUNT.as_bytes(),
+            exchange_key.as_ref(),
+            user_key.as_ref(),
+            &[user_bump],
+        ],
+        &[
+            PREFIX_SERUM_MARKET_AUTH.as_bytes(),
+            exchange_key.as_ref(),
+            &[bump],
+        ],
+    ];
+
+    let mut instruments: Vec<Pubkey> = vec![];
+    for (leg, accounts) in legs.iter().zip(legs_accounts.chunks(COMBO_LEG_ACCOUNTS)) {
+        let optifi_market_info = &accounts[0];
+        let serum_market = &accounts[1];
+        let open_orders = &accounts[2];
+        let request_queue = &accounts[3];
+        let event_queue = &accounts[4];
+        let market_bids = &accounts[5];
+        let market_asks = &accounts[6];
+        let coin_mint = &accounts[7];
+        let coin_vault = &accounts[8];
+        let pc_vault = &accounts[9];
+        let vault_signer = &accounts[10];
+        let user_instrument_long_token_vault = &accounts[11];
+        let user_instrument_short_token_vault = &accounts[12];
+        let instrument_short_spl_token_mint = &accounts[13];
+
+        if optifi_market_info.owner != ctx.program_id
+            || !optifi_exchange
+                .markets
+                .iter()
+                .any(|m| m.optifi_market_pubkey == optifi_market_info.key())
+        {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        let optifi_market =
+            OptifiMarket::try_deserialize(&mut &optifi_market_info.data.borrow()[..])?;
+        if optifi_market.is_stopped
+            || optifi_market.serum_market != serum_market.key()
+            || optifi_market.instrument_long_spl_token != coin_mint.key()
+            || optifi_market.instrument_short_spl_token != instrument_short_spl_token_mint.key()
+            || accessor::mint(user_instrument_long_token_vault)?
+                != optifi_market.instrument_long_spl_token
+            || accessor::authority(user_instrument_long_token_vault)? != user_account_info.key()
+            || accessor::mint(user_instrument_short_token_vault)?
+                != optifi_market.instrument_short_spl_token
+            || accessor::authority(user_instrument_short_token_vault)? != user_account_info.key()
+        {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+            != Some(margin_stress_account.asset)
+        {
+            return Err(ErrorCode::WrongAsset.into());
+        }
+        if instruments.contains(&optifi_market.instrument) {
+            return Err(ErrorCode::InvalidComboOrder.into());
+        }
+        instruments.push(optifi_market.instrument);
+
+        let coin_lot_size = Market::load(serum_market, serum_market.owner)?.coin_lot_size;
+        let order_coin_qty = leg
+            .max_coin_qty
+            .checked_mul(coin_lot_size)
+            .ok_or(ErrorCode::NumericalOverflowError)?;
+
+        let (serum_side, order_payer) = match leg.side {
+            OrderSide::Bid => (Side::Bid, user_margin_account),
+            OrderSide::Ask => {
+                // the seller sells the long tokens and keeps the short tokens
+                mint_instrument_token_for_user(
+                    coin_mint,
+                    user_instrument_long_token_vault,
+                    order_coin_qty,
+                    token_program,
+                    ctx.program_id,
+                    &exchange_key,
+                    &ctx.accounts.instrument_token_mint_authority_pda,
+                )?;
+                mint_instrument_token_for_user(
+                    instrument_short_spl_token_mint,
+                    user_instrument_short_token_vault,
+                    order_coin_qty,
+                    token_program,
+                    ctx.program_id,
+                    &exchange_key,
+                    &ctx.accounts.instrument_token_mint_authority_pda,
+                )?;
+                (Side::Ask, user_instrument_long_token_vault)
+            }
+        };
+
+        check_linked_self_trade(
+            ctx.program_id,
+            user_account,
+            serum_market,
+            market_bids,
+            market_asks,
+            serum_side,
+            leg.limit,
+        )?;
+        let payer_amount = accessor::amount(order_payer)?;
+        let balances_before_order =
+            serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+        serum_new_order_with_client_order_id(
+            signer_seeds,
+            serum_market,
+            open_orders,
+            request_queue,
+            event_queue,
+            market_bids,
+            market_asks,
+            order_payer,
+            &user_account_info,
+            coin_vault,
+            pc_vault,
+            token_program,
+            rent,
+            dex_program,
+            serum_side,
+            leg.limit,
+            leg.max_coin_qty,
+            OrderType::ImmediateOrCancel,
+            0,
+            user_account.self_trade_mode.to_serum_self_trade_behavior(),
+            leg.max_pc_qty,
+            ctx.program_id,
+            &exchange_key,
+        )?;
+        let deposit = payer_amount.saturating_sub(accessor::amount(order_payer)?);
+        let balances_after_order =
+            serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+        let (taker_qty, taker_cost) = taker_fill(
+            serum_side,
+            balances_before_order,
+            balances_after_order,
+            deposit,
+        );
+
+        // every leg must be filled in full, otherwise the whole combo is reverted
+        let filled_coin_qty = taker_qty.unsigned_abs();
+        msg!("leg filled {} of {}", filled_coin_qty, order_coin_qty);
+        if filled_coin_qty < order_coin_qty {
+            return Err(ErrorCode::OrderNotFilled.into());
+        }
+
+        serum_settle_funds_for_user(
+            signer_seeds[0],
+            dex_program,
+            serum_market,
+            token_program,
+            open_orders,
+            &user_account_info,
+            coin_vault,
+            user_instrument_long_token_vault,
+            pc_vault,
+            user_margin_account,
+            vault_signer,
+            ctx.program_id,
+        )?;
+        let balances_after_settlement =
+            serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+        let open_bid_qty =
+            serum_open_bid_quantity(serum_market, open_orders, &user_account_info, market_bids)?;
+
+        user_account.record_open_orders_fills(
+            optifi_market.instrument,
+            balances_before_order,
+            balances_after_settlement,
+        );
+        user_account.record_taker_fill(optifi_market.instrument, taker_qty, taker_cost);
+        user_account.update_long_position(
+            optifi_market.instrument,
+            accessor::amount(user_instrument_long_token_vault)?,
+        );
+        user_account.update_short_position(
+            optifi_market.instrument,
+            accessor::amount(user_instrument_short_token_vault)?,
+        );
+        user_account.update_open_bid_quantity(optifi_market.instrument, open_bid_qty);
+    }
+
+    // the margin is checked once, on the portfolio with all the legs filled
+    let margin_result = update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        stress_accounts,
+    )?;
+    user_account.update_health(user_margin_account);
+    msg!("margin breakdown : {:?}", margin_result);
+
+    if !is_margin_sufficient(user_margin_account, user_account) {
+        return Err(ErrorCode::InsufficientMargin.into());
+    }
+
+    Ok(())
+}
@@ -1,11 +1,19 @@
+pub mod cancel_all_orders;
 pub mod cancel_order;
+pub mod combo_order;
 pub mod instrument_spl_token_utils;
+pub mod order_expiry;
 pub mod order_settlement;
 pub mod place_order;
+pub mod replace_order;
 pub mod serum_utils;
 
+pub use cancel_all_orders::*;
 pub use cancel_order::*;
+pub use combo_order::*;
 pub use instrument_spl_token_utils::*;
+pub use order_expiry::*;
 pub use order_settlement::*;
 pub use place_order::*;
+pub use replace_order::*;
 pub use serum_utils::*;
@@ -0,0 +1,386 @@
+use crate::constants::CRANKER_FEE;
+use crate::errors::ErrorCode;
+use crate::instructions::user::refresh_user_margin;
+use crate::state::{MarginStressAccount, OptifiMarket, OrderMetadata, UserAccount};
+use crate::utils::{PREFIX_ORDER_METADATA, PREFIX_USER_ACCOUNT};
+use crate::{Exchange, OrderSide, OrderType};
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, accessor, Transfer};
+use serum_dex::matching::Side;
+use serum_dex::state::Market;
+
+use super::{
+    cancel_order_on_market, iter_filled_slots, orders_with_client_ids, CancelMarketAccounts,
+    PlaceOrderContext,
+};
+
+/// Accounts used to place an order which expires at a given time
+#[derive(Accounts)]
+#[instruction(client_order_id: u64, bump: u8)]
+pub struct PlaceOrderWithExpiryContext<'info> {
+    pub place_order: PlaceOrderContext<'info>,
+    /// the metadata of the new order, it's closed when the order is cancelled after expiry
+    #[account(init,
+        seeds=[
+            PREFIX_ORDER_METADATA.as_bytes(),
+            place_order.user_account.key().as_ref(),
+            &client_order_id.to_le_bytes()
+        ],
+        payer=payer,
+        bump=bump,
+        space=8+32+32+8+16+8+1
+    )]
+    pub order_metadata: ProgramAccount<'info, OrderMetadata>,
+    #[account(mut, signer)]
+    pub payer: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Place an order which can be cancelled by anyone after `expiry`
+pub fn handle_place_order_with_expiry(
+    ctx: Context<PlaceOrderWithExpiryContext>,
+    client_order_id: u64,
+    bump: u8,
+    side: OrderSide,
+    limit: u64,
+    max_coin_qty: u64,
+    max_pc_qty: u64,
+    order_type: OrderType,
+    expiry: u64,
+) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp as u64;
+    // the order is found by its client order id, and an immediate order never rests
+    if expiry <= now || client_order_id == 0 || order_type.is_immediate() {
+        return Err(ErrorCode::InvalidOrderExpiry.into());
+    }
+
+    // the user can have other orders of the same client order id,
+    // the new order is the one which wasn't on the orderbook before
+    let orders_before_order = order_ids_of_client_order_id(
+        &ctx.accounts.place_order.serum_market,
+        &ctx.accounts.place_order.open_orders,
+        &ctx.accounts.place_order.user_account.to_account_info(),
+        client_order_id,
+    )?;
+    super::place_order::handle(
+        Context::new(
+            ctx.program_id,
+            &mut ctx.accounts.place_order,
+            ctx.remaining_accounts,
+        ),
+        side,
+        limit,
+        max_coin_qty,
+        max_pc_qty,
+        client_order_id,
+        order_type,
+        false,
+    )?;
+    let order_id = order_ids_of_client_order_id(
+        &ctx.accounts.place_order.serum_market,
+        &ctx.accounts.place_order.open_orders,
+        &ctx.accounts.place_order.user_account.to_account_info(),
+        client_order_id,
+    )?
+    .into_iter()
+    .find(|order_id| !orders_before_order.contains(order_id))
+    .unwrap_or(0);
+
+    let order_metadata = &mut ctx.accounts.order_metadata;
+    order_metadata.user_account = ctx.accounts.place_order.user_account.key();
+    order_metadata.optifi_market = ctx.accounts.place_order.optifi_market.key();
+    order_metadata.client_order_id = client_order_id;
+    order_metadata.order_id = order_id;
+    order_metadata.expiry = expiry;
+    order_metadata.bump = bump;
+    msg!("order {} expires at {}", order_id, expiry);
+    Ok(())
+}
+
+/// the serum order ids of the user's orders of `client_order_id` on the market
+pub fn order_ids_of_client_order_id(
+    serum_market: &AccountInfo,
+    open_orders: &AccountInfo,
+    user_account_info: &AccountInfo,
+    client_order_id: u64,
+) -> Result<Vec<u128>, ProgramError> {
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let open_orders = market.load_orders_mut(
+        open_orders,
+        Some(user_account_info),
+        serum_market.owner,
+        None,
+        None,
+    )?;
+    Ok(orders_with_client_ids(*open_orders)
+        .filter(|(id, _, _)| id.get() == client_order_id)
+        .map(|(_, order_id, _)| order_id)
+        .collect())
+}
+
+/// Accounts used by the crank which cancels expired orders
+#[derive(Accounts)]
+pub struct CancelExpiredOrderContext<'info> {
+    /// optifi_exchange account
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// margin stress account of the instrument's asset
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+    /// the cranker, who is paid the CRANKER_FEE for a cancelled order
+    #[account(signer)]
+    pub cranker: AccountInfo<'info>,
+    /// the cranker's usdc account to receive the fee
+    #[account(mut)]
+    pub cranker_usdc_account: AccountInfo<'info>,
+    /// the user's wallet, which receives the rent of the order metadata account
+    #[account(mut)]
+    pub user: AccountInfo<'info>,
+    /// user's optifi account
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key() && user_account.owner == user.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    /// user's margin account which is controlled by a pda
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
+    pub user_margin_account: AccountInfo<'info>,
+    /// the metadata of the expired order
+    #[account(mut, close = user,
+        constraint = order_metadata.user_account == user_account.key() && order_metadata.optifi_market == optifi_market.key()
+    )]
+    pub order_metadata: ProgramAccount<'info, OrderMetadata>,
+    /// user's instrument long spl token account which is controlled by the user's user account(pda)
+    #[account(mut, constraint = accessor::mint(&user_instrument_long_token_vault)? == optifi_market.instrument_long_spl_token
+        && accessor::authority(&user_instrument_long_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
+    pub user_instrument_long_token_vault: AccountInfo<'info>,
+    /// user's instrument short spl token account which is controlled by the user's user account(pda)
+    #[account(mut, constraint = accessor::mint(&user_instrument_short_token_vault)? == optifi_market.instrument_short_spl_token
+        && accessor::authority(&user_instrument_short_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
+    pub user_instrument_short_token_vault: AccountInfo<'info>,
+    /// optifi market that binds an instrument with a serum market(orderbook)
+    #[account(constraint = optifi_exchange.markets.iter().any(|m| m.optifi_market_pubkey == optifi_market.key()))]
+    pub optifi_market: ProgramAccount<'info, OptifiMarket>,
+    /// the serum market(orderbook)
+    #[account(mut, constraint = optifi_market.serum_market == serum_market.key())]
+    pub serum_market: AccountInfo<'info>,
+    /// the user's open orders account
+    #[account(mut)]
+    pub open_orders: AccountInfo<'info>,
+    #[account(mut)]
+    pub event_queue: AccountInfo<'info>,
+    #[account(mut)]
+    pub bids: AccountInfo<'info>,
+    #[account(mut)]
+    pub asks: AccountInfo<'info>,
+    /// The token mint address of "base" currency, aka the instrument long spl token
+    #[account(mut, constraint = optifi_market.instrument_long_spl_token == coin_mint.key())]
+    pub coin_mint: AccountInfo<'info>,
+    /// The vault for the "base" currency
+    #[account(mut)]
+    pub coin_vault: AccountInfo<'info>,
+    /// The vault for the "quote" currency
+    #[account(mut)]
+    pub pc_vault: AccountInfo<'info>,
+    /// serum market vault owner (pda)
+    pub vault_signer: AccountInfo<'info>,
+    /// the instrument short spl token
+    #[account(mut, constraint = optifi_market.instrument_short_spl_token == instrument_short_spl_token_mint.key())]
+    pub instrument_short_spl_token_mint: AccountInfo<'info>,
+    pub serum_dex_program_id: AccountInfo<'info>,
+    #[account(address = token::ID)]
+    pub token_program: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // ====================================================================
+}
+
+/// Cancel an expired order - for crankers to call
+///
+/// The order is cancelled by its serum order id, and the cranker is paid the CRANKER_FEE
+/// from the user's margin in excess of the maintenance margin. If the order doesn't rest
+/// on the orderbook anymore, only the order metadata is closed, which can be done before
+/// the expiry as well.
+pub fn handle_cancel_expired_order(ctx: Context<CancelExpiredOrderContext>) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp as u64;
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let optifi_market = &ctx.accounts.optifi_market;
+    let order_metadata = &ctx.accounts.order_metadata;
+    let user_account = &mut ctx.accounts.user_account;
+    let user_margin_account = &ctx.accounts.user_margin_account;
+    let dex_program = &ctx.accounts.serum_dex_program_id;
+
+    if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+        != Some(ctx.accounts.margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
+    let order_id = order_metadata.order_id;
+    let user_account_info = user_account.to_account_info();
+    let resting_side = {
+        let market = Market::load(&ctx.accounts.serum_market, dex_program.key)?;
+        let open_orders = market.load_orders_mut(
+            &ctx.accounts.open_orders,
+            Some(&user_account_info),
+            dex_program.key,
+            None,
+            None,
+        )?;
+        iter_filled_slots(*open_orders)
+            .find(|&slot| order_id != 0 && open_orders.orders[slot as usize] == order_id)
+            .and_then(|slot| open_orders.slot_side(slot))
+    };
+    let side = match resting_side {
+        Some(Side::Bid) => OrderSide::Bid,
+        Some(Side::Ask) => OrderSide::Ask,
+        None => {
+            msg!("order {} is not on the orderbook", order_id);
+            return Ok(());
+        }
+    };
+    if !order_metadata.is_expired(now) {
+        return Err(ErrorCode::OrderNotExpired.into());
+    }
+
+    let exchange_key = optifi_exchange.key();
+    cancel_order_on_market(
+        ctx.program_id,
+        &exchange_key,
+        dex_program,
+        &ctx.accounts.token_program,
+        user_account,
+        &user_account_info,
+        user_margin_account,
+        &CancelMarketAccounts {
+            instrument: optifi_market.instrument,
+            serum_market: &ctx.accounts.serum_market,
+            open_orders: &ctx.accounts.open_orders,
+            event_queue: &ctx.accounts.event_queue,
+            bids: &ctx.accounts.bids,
+            asks: &ctx.accounts.asks,
+            coin_mint: &ctx.accounts.coin_mint,
+            coin_vault: &ctx.accounts.coin_vault,
+            pc_vault: &ctx.accounts.pc_vault,
+            vault_signer: &ctx.accounts.vault_signer,
+            user_instrument_long_token_vault: &ctx.accounts.user_instrument_long_token_vault,
+            user_instrument_short_token_vault: &ctx.accounts.user_instrument_short_token_vault,
+            instrument_short_spl_token_mint: &ctx.accounts.instrument_short_spl_token_mint,
+        },
+        side,
+        order_id,
+    )?;
+    msg!("cancelled expired order {}", order_id);
+
+    // release the margin reserved for the cancelled order
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+
+    // the cranker is paid from the user's margin in excess of the maintenance margin,
+    // so the crank never makes the user liquidatable
+    let cranker_fee = CRANKER_FEE.min(
+        user_account
+            .get_equity(user_margin_account)
+            .saturating_sub(user_account.get_maintanance_margin()),
+    );
+    if cranker_fee > 0 {
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.clone(),
+                Transfer {
+                    from: user_margin_account.clone(),
+                    to: ctx.accounts.cranker_usdc_account.clone(),
+                    authority: user_account_info.clone(),
+                },
+            )
+            .with_signer(&[&[
+                PREFIX_USER_ACCOUNT.as_bytes(),
+                exchange_key.as_ref(),
+                user_account.owner.as_ref(),
+                &[user_account.bump],
+            ]]),
+            cranker_fee,
+        )?;
+        user_account.update_health(user_margin_account);
+    }
+    Ok(())
+}
+
+/// Split the order metadata accounts in `remaining_accounts` from the other accounts
+pub fn split_order_metadata_accounts<'info>(
+    program_id: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> (Vec<AccountInfo<'info>>, Vec<AccountInfo<'info>>) {
+    remaining_accounts.iter().cloned().partition(|account| {
+        account.owner == program_id
+            && OrderMetadata::try_deserialize(&mut &account.data.borrow()[..]).is_ok()
+    })
+}
+
+/// The order metadata account of the user's order `order_id` on the optifi market,
+/// if it's one of `order_metadata_accounts`
+pub fn find_order_metadata_account<'a, 'info>(
+    optifi_market: &Pubkey,
+    user_account: &Pubkey,
+    order_metadata_accounts: &'a [AccountInfo<'info>],
+    order_id: u128,
+) -> Result<Option<&'a AccountInfo<'info>>, ProgramError> {
+    for account in order_metadata_accounts {
+        let order_metadata = OrderMetadata::try_deserialize(&mut &account.data.borrow()[..])?;
+        if order_metadata.user_account == *user_account
+            && order_metadata.optifi_market == *optifi_market
+            && order_metadata.order_id == order_id
+        {
+            return Ok(Some(account));
+        }
+    }
+    Ok(None)
+}
+
+/// Close the order metadata accounts of the user's orders on the optifi market
+/// which don't rest on the orderbook anymore, the rent is returned to the user's wallet
+pub fn close_order_metadata_accounts<'info>(
+    optifi_market: &Pubkey,
+    serum_market: &AccountInfo<'info>,
+    open_orders: &AccountInfo<'info>,
+    user_account_info: &AccountInfo<'info>,
+    user: &AccountInfo<'info>,
+    order_metadata_accounts: &[AccountInfo<'info>],
+) -> ProgramResult {
+    if order_metadata_accounts.is_empty() {
+        return Ok(());
+    }
+    let resting_order_ids = {
+        let market = Market::load(serum_market, serum_market.owner)?;
+        let open_orders = market.load_orders_mut(
+            open_orders,
+            Some(user_account_info),
+            serum_market.owner,
+            None,
+            None,
+        )?;
+        iter_filled_slots(*open_orders)
+            .map(|slot| open_orders.orders[slot as usize])
+            .collect::<Vec<u128>>()
+    };
+
+    for account in order_metadata_accounts {
+        let order_metadata = OrderMetadata::try_deserialize(&mut &account.data.borrow()[..])?;
+        if order_metadata.user_account != user_account_info.key() {
+            return Err(ErrorCode::InvalidAccount.into());
+        }
+        if order_metadata.optifi_market != *optifi_market
+            || resting_order_ids.contains(&order_metadata.order_id)
+        {
+            continue;
+        }
+        **user.try_borrow_mut_lamports()? += account.lamports();
+        **account.try_borrow_mut_lamports()? = 0;
+        account.try_borrow_mut_data()?.fill(0);
+        msg!("closed the metadata of order {}", order_metadata.order_id);
+    }
+    Ok(())
+}
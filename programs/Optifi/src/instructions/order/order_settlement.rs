@@ -1,6 +1,11 @@
-use crate::state::{OptifiMarket, UserAccount};
+use crate::errors::ErrorCode;
+use crate::instructions::user::refresh_user_margin;
+use crate::state::{MarginStressAccount, OptifiMarket, ReferrerAccount, UserAccount};
 use crate::utils::PREFIX_USER_ACCOUNT;
-use crate::{serum_settle_funds_for_user, Exchange};
+use crate::{
+    pay_fees, serum_open_bid_quantity, serum_open_orders_balances,
+    serum_settle_funds_with_referrer_for_user, Exchange,
+};
 use anchor_lang::prelude::*;
 use anchor_spl::token::{accessor, Token};
 use solana_program::log::sol_log_compute_units;
@@ -11,6 +16,10 @@ use super::burn_instrument_token_for_user;
 pub struct OrderSettlement<'info> {
     pub optifi_exchange: ProgramAccount<'info, Exchange>,
 
+    /// margin stress account of the instrument's asset
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+
     #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key())]
     pub user_account: ProgramAccount<'info, UserAccount>,
 
@@ -23,30 +32,49 @@ pub struct OrderSettlement<'info> {
     #[account(mut)]
     pub user_serum_open_orders: AccountInfo<'info>,
 
+    /// the bids of the serum market, to get the resting bids of the user
+    #[account(mut)]
+    pub bids: AccountInfo<'info>,
+
     #[account(mut)]
     pub coin_vault: AccountInfo<'info>,
 
     #[account(mut)]
     pub pc_vault: AccountInfo<'info>,
 
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_long_spl_token == instrument_long_spl_token_mint.key())]
     pub instrument_long_spl_token_mint: AccountInfo<'info>,
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_short_spl_token == instrument_short_spl_token_mint.key())]
     pub instrument_short_spl_token_mint: AccountInfo<'info>,
-    #[account(mut)]
+    /// user's instrument long spl token account which is controlled by the user's user account(pda)
+    #[account(mut, constraint = accessor::mint(&user_instrument_long_token_vault)? == optifi_market.instrument_long_spl_token
+        && accessor::authority(&user_instrument_long_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_long_token_vault: AccountInfo<'info>,
-    #[account(mut)]
+    /// user's instrument short spl token account which is controlled by the user's user account(pda)
+    #[account(mut, constraint = accessor::mint(&user_instrument_short_token_vault)? == optifi_market.instrument_short_spl_token
+        && accessor::authority(&user_instrument_short_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_short_token_vault: AccountInfo<'info>,
 
-    #[account(mut)]
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
     pub user_margin_account: AccountInfo<'info>,
 
     #[account(mut)]
     pub vault_signer: AccountInfo<'info>,
 
+    /// the protocol fee vault which the user's trading fees are collected into
+    #[account(mut, constraint = fee_vault.key() == optifi_exchange.fee_vault @ ErrorCode::InvalidAccount)]
+    pub fee_vault: AccountInfo<'info>,
+
     pub token_program: Program<'info, Token>,
 
     pub serum_dex_program_id: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // if the user has a referrer, pass the referrer account and its rebate
+    // vault as the first two accounts of ctx.remaining_accounts, the referrer
+    // gets a share of the user's fees and the serum referral rebates.
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts after them
+    // ====================================================================
 }
 
 pub fn handler(ctx: Context<OrderSettlement>) -> ProgramResult {
@@ -65,13 +93,56 @@ pub fn handler(ctx: Context<OrderSettlement>) -> ProgramResult {
     let instrument_short_spl_token_mint = &ctx.accounts.instrument_short_spl_token_mint;
     let user_margin_account = &ctx.accounts.user_margin_account;
 
+    if optifi_exchange.get_instrument_asset(&ctx.accounts.optifi_market.instrument)
+        != Some(ctx.accounts.margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
     let signer_seeds = &[
         PREFIX_USER_ACCOUNT.as_bytes(),
         optifi_exchange.to_account_info().key.as_ref(),
         user_account.owner.as_ref(),
         &[user_account.bump],
     ];
-    serum_settle_funds_for_user(
+
+    // the referrer of the user, with its account data to pay the rebates, and its rebate vault
+    let mut referrer = None;
+    let mut stress_accounts = ctx.remaining_accounts;
+    if user_account.referrer != Pubkey::default() {
+        if ctx.remaining_accounts.len() < 2 {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        let (referrer_accounts, other_accounts) = ctx.remaining_accounts.split_at(2);
+        let referrer_account_info = &referrer_accounts[0];
+        let referrer_rebate_vault = &referrer_accounts[1];
+        if referrer_account_info.key() != user_account.referrer
+            || referrer_account_info.owner != ctx.program_id
+            || !referrer_account_info.is_writable
+            || !referrer_rebate_vault.is_writable
+        {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        let referrer_account =
+            ReferrerAccount::try_deserialize(&mut &referrer_account_info.data.borrow()[..])?;
+        if referrer_account.rebate_vault != referrer_rebate_vault.key() {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        referrer = Some((
+            referrer_account,
+            referrer_account_info,
+            referrer_rebate_vault,
+        ));
+        stress_accounts = other_accounts;
+    }
+    let referrer_rebate_vault = referrer.as_ref().map(|&(_, _, vault)| vault);
+
+    let balances_before_settlement = serum_open_orders_balances(
+        serum_market,
+        user_serum_open_orders,
+        &user_account.to_account_info(),
+    )?;
+    serum_settle_funds_with_referrer_for_user(
         // &user_account.owner,
         signer_seeds,
         dex_program,
@@ -85,9 +156,18 @@ pub fn handler(ctx: Context<OrderSettlement>) -> ProgramResult {
         pc_vault,
         user_margin_account,
         vault_signer,
-        &ctx.program_id,
-        // &optifi_exchange.key(),
+        referrer_rebate_vault,
+    )?;
+    let balances_after_settlement = serum_open_orders_balances(
+        serum_market,
+        user_serum_open_orders,
+        &user_account.to_account_info(),
     )?;
+    user_account.record_open_orders_fills(
+        ctx.accounts.optifi_market.instrument,
+        balances_before_settlement,
+        balances_after_settlement,
+    );
 
     sol_log_compute_units();
 
@@ -149,6 +229,75 @@ pub fn handler(ctx: Context<OrderSettlement>) -> ProgramResult {
     );
 
     user_account.update_long_position(optifi_market.instrument, long_amount);
+    user_account.update_short_position(optifi_market.instrument, short_amount);
+
+    let open_bid_qty = serum_open_bid_quantity(
+        serum_market,
+        user_serum_open_orders,
+        &user_account.to_account_info(),
+        &ctx.accounts.bids,
+    )?;
+    user_account.update_open_bid_quantity(optifi_market.instrument, open_bid_qty);
+
+    // collect the maker and taker fees of the fills into the protocol fee vault,
+    // what the margin account can't cover stays unpaid until the next settlement
+    let fees = user_account
+        .unpaid_fees
+        .min(accessor::amount(user_margin_account)?);
+    if fees > 0 && optifi_exchange.fee_vault != Pubkey::default() {
+        let exchange_key = optifi_exchange.key();
+        let user_key = user_account.owner;
+        let user_bump = [user_account.bump];
+        let seeds: &[&[&[u8]]] = &[&[
+            PREFIX_USER_ACCOUNT.as_bytes(),
+            exchange_key.as_ref(),
+            user_key.as_ref(),
+            &user_bump,
+        ]];
+        // the referrer's share of the fees is paid into its rebate vault
+        let rebate = match referrer {
+            Some(_) => fees * optifi_exchange.referral_fee_share_bps / 10_000,
+            None => 0,
+        };
+        pay_fees(
+            fees - rebate,
+            token_program.to_account_info(),
+            user_margin_account.clone(),
+            user_account.to_account_info(),
+            ctx.accounts.fee_vault.clone(),
+            Some(seeds),
+        )?;
+        if let Some((mut referrer_account, referrer_account_info, referrer_rebate_vault)) = referrer
+        {
+            if rebate > 0 {
+                pay_fees(
+                    rebate,
+                    token_program.to_account_info(),
+                    user_margin_account.clone(),
+                    user_account.to_account_info(),
+                    referrer_rebate_vault.clone(),
+                    Some(seeds),
+                )?;
+                referrer_account.total_rebates += rebate;
+                referrer_account
+                    .try_serialize(&mut &mut referrer_account_info.data.borrow_mut()[..])?;
+            }
+        }
+        user_account.unpaid_fees -= fees;
+        msg!(
+            "collected {} usdc of trading fees, {} to the referrer",
+            fees,
+            rebate
+        );
+    }
 
-    Ok(())
+    // the filled orders are now positions, recalculate the margin on them
+    refresh_user_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        &ctx.accounts.user_margin_account,
+        &ctx.accounts.margin_stress_account,
+        stress_accounts,
+    )
 }
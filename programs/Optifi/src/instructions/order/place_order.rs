@@ -1,20 +1,22 @@
-use crate::constants::{SECS_IN_STANDARD_YEAR, USDC_DECIMALS};
+use crate::constants::{MARKET_ORDER_FEE_BUFFER_BPS, USDC_DECIMALS};
 use crate::errors::ErrorCode;
-use crate::financial::margin::margin_function;
+use crate::financial::margin::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
 use crate::instructions::order::serum_utils::serum_new_order_with_client_order_id;
 use crate::instrument_spl_token_utils::mint_instrument_token_for_user;
-use crate::serum_utils::serum_new_order;
-use crate::utils::{
-    get_central_usdc_pool_auth_pda, get_serum_market_auth_pda, PREFIX_CENTRAL_USDC_POOL_AUTH,
-    PREFIX_SERUM_MARKET_AUTH, PREFIX_USER_ACCOUNT,
+use crate::instrument_spl_token_utils::burn_instrument_token_for_user;
+use crate::serum_utils::{
+    serum_crosses_open_orders, serum_new_order, serum_open_bid_quantity,
+    serum_open_orders_balances, serum_open_orders_count, serum_settle_funds_for_user,
 };
+use crate::utils::{get_serum_market_auth_pda, PREFIX_SERUM_MARKET_AUTH, PREFIX_USER_ACCOUNT};
 
 use crate::state::{MarginStressAccount, OptifiMarket};
-use crate::state::{MarginStressState, UserAccount};
-use crate::{ceil, i_to_f_repr, pay_fees, u_to_f_repr, Exchange, OrderSide};
+use crate::state::{OpenOrdersBalances, UserAccount};
+use crate::{ceil, i_to_f_repr, u_to_f_repr, Exchange, OrderSide, OrderType};
 use anchor_lang::prelude::*;
 use anchor_spl::token::{self, accessor};
-use serum_dex::matching::{OrderType, Side};
+use serum_dex::matching::Side;
 use serum_dex::state::Market;
 use solana_program::log::sol_log_compute_units;
 
@@ -25,29 +27,32 @@ pub struct PlaceOrderContext<'info> {
     pub optifi_exchange: ProgramAccount<'info, Exchange>,
     #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
     pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
-    /// the user's wallet
-    #[account(signer)]
+    /// the user's wallet, which receives the rent of the closed order metadata accounts
+    #[account(mut, signer)]
     pub user: AccountInfo<'info>,
     /// user's optifi account
-    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key())]
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key()
+        && user_account.owner == user.key() @ ErrorCode::UnauthorizedAccount)]
     pub user_account: ProgramAccount<'info, UserAccount>,
     /// user's margin account which is controlled by a pda
-    #[account(mut)]
+    #[account(mut, constraint = user_account.user_margin_account_usdc == user_margin_account.key())]
     pub user_margin_account: AccountInfo<'info>,
     /// user's instrument long spl token account which is controlled by a the user's user account(pda)
     /// it stands for how many contracts the user sold for the instrument
     /// and it should be the same as order_payer_token_account if the order is ask order
-    #[account(mut)]
+    #[account(mut, constraint = accessor::mint(&user_instrument_long_token_vault)? == optifi_market.instrument_long_spl_token
+        && accessor::authority(&user_instrument_long_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_long_token_vault: AccountInfo<'info>,
     /// user's instrument short spl token account which is controlled by a the user's user account(pda)
     /// it stands for how many contracts the user bought for the instrument
-    #[account(mut)]
+    #[account(mut, constraint = accessor::mint(&user_instrument_short_token_vault)? == optifi_market.instrument_short_spl_token
+        && accessor::authority(&user_instrument_short_token_vault)? == user_account.key() @ ErrorCode::InvalidAccount)]
     pub user_instrument_short_token_vault: AccountInfo<'info>,
     /// optifi market that binds an instrument with a serum market(orderbook)
     /// it's also the mint authority of the instrument spl token
     pub optifi_market: ProgramAccount<'info, OptifiMarket>,
     /// the serum market(orderbook)
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.serum_market == serum_market.key())]
     pub serum_market: AccountInfo<'info>,
     /// the user's open orders account
     #[account(mut)]
@@ -62,7 +67,7 @@ pub struct PlaceOrderContext<'info> {
     #[account(mut)]
     pub asks: AccountInfo<'info>,
     /// The token mint address of "base" currency, aka the instrument long spl token
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_long_spl_token == coin_mint.key())]
     pub coin_mint: AccountInfo<'info>,
     /// The vault for the "base" currency
     #[account(mut)]
@@ -81,7 +86,7 @@ pub struct PlaceOrderContext<'info> {
     #[account(constraint = usdc_central_pool.key() == optifi_exchange.usdc_central_pool)]
     pub usdc_central_pool: AccountInfo<'info>,
     /// the instrument short spl token
-    #[account(mut)]
+    #[account(mut, constraint = optifi_market.instrument_short_spl_token == instrument_short_spl_token_mint.key())]
     pub instrument_short_spl_token_mint: AccountInfo<'info>,
     pub serum_dex_program_id: AccountInfo<'info>,
     #[account(address = token::ID)]
@@ -89,6 +94,12 @@ pub struct PlaceOrderContext<'info> {
     pub rent: Sysvar<'info, Rent>,
     // // Clock to get the timestamp
     // pub clock: Sysvar<'info, Clock>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // to replace a good-til-time order, pass its order metadata account
+    // into ctx.remaining_accounts as well, its expiry is carried over
+    // ====================================================================
 }
 
 pub fn handle(
@@ -98,11 +109,15 @@ pub fn handle(
     max_coin_qty: u64,
     max_pc_qty: u64,
     client_order_id: u64,
+    order_type: OrderType,
+    reduce_only: bool,
 ) -> ProgramResult {
-    if ctx.accounts.margin_stress_account.state != MarginStressState::Available {
-        return Err(ErrorCode::WrongState.into());
-    }
+    let now = Clock::get()?.unix_timestamp as u64;
     let optifi_exchange = &ctx.accounts.optifi_exchange;
+    ctx.accounts.margin_stress_account.check_available(
+        now,
+        optifi_exchange.instruments_epoch[ctx.accounts.margin_stress_account.asset as usize],
+    )?;
     let user_account = &mut ctx.accounts.user_account;
     let serum_market = &ctx.accounts.serum_market;
     let coin_mint = &ctx.accounts.coin_mint;
@@ -131,80 +146,112 @@ pub fn handle(
         return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
     }
 
-    //pay_order_fees(&ctx, limit)?;
+    // the order quantity is kept in native coin, as the positions are,
+    // and converted to coin lots for serum only
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let (coin_lot_size, pc_lot_size) = (market.coin_lot_size, market.pc_lot_size);
+    drop(market);
+    let order_coin_qty = max_coin_qty
+        .checked_mul(coin_lot_size)
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+
+    // a reduce-only order is capped at the user's net position on the other side,
+    // less what the resting bids already buy back for a bid,
+    // an ask sells the long tokens the user already holds
+    let (order_coin_qty, max_pc_qty) = if reduce_only {
+        let net_qty = user_account.get_quantity(optifi_market.instrument);
+        let reducible_qty = match side {
+            OrderSide::Bid => {
+                let open_bid_qty = serum_open_bid_quantity(
+                    serum_market,
+                    open_orders,
+                    &user_account.to_account_info(),
+                    market_bids,
+                )?;
+                (-net_qty - open_bid_qty as i64).max(0) as u64
+            }
+            OrderSide::Ask => {
+                (net_qty.max(0) as u64).min(accessor::amount(user_instrument_long_token_vault)?)
+            }
+        };
+        let capped_qty = order_coin_qty.min(reducible_qty - reducible_qty % coin_lot_size);
+        if capped_qty == 0 {
+            return Err(ErrorCode::ReduceOnlyOrderRejected.into());
+        }
+        msg!("reduce only order of {}", capped_qty);
+        // a reduce-only bid can't lock more pc than buying back the capped quantity
+        let max_pc_qty = match side {
+            OrderSide::Bid => {
+                let native_pc_qty = limit
+                    .checked_mul(pc_lot_size)
+                    .and_then(|pc| pc.checked_mul(capped_qty / coin_lot_size))
+                    .ok_or(ErrorCode::NumericalOverflowError)?;
+                max_pc_qty.min(native_pc_qty + native_pc_qty * MARKET_ORDER_FEE_BUFFER_BPS / 10000)
+            }
+            OrderSide::Ask => max_pc_qty,
+        };
+        (capped_qty, max_pc_qty)
+    } else {
+        (order_coin_qty, max_pc_qty)
+    };
 
     // 0 is bid, 1 is ask - for the purpose of this, anything non-zero will be interpreted as ask
     let serum_side = match side {
-        OrderSide::Bid => Side::Bid,
+        OrderSide::Bid => {
+            // the new bid is counted with the resting bids as potential long position
+            let open_bid_qty = serum_open_bid_quantity(
+                serum_market,
+                open_orders,
+                &user_account.to_account_info(),
+                market_bids,
+            )?;
+            user_account
+                .update_open_bid_quantity(optifi_market.instrument, open_bid_qty + order_coin_qty);
+            Side::Bid
+        }
         OrderSide::Ask => {
-            user_account.add_short_position(optifi_market.instrument, max_coin_qty);
+            if !reduce_only {
+                user_account.add_short_position(optifi_market.instrument, order_coin_qty);
+            }
             Side::Ask
         }
     };
 
     sol_log_compute_units();
     // Margin calculation
-    let mut positions = vec![];
-
-    for pubkey in &margin_stress_account.instruments {
-        if let Some(p) = user_account
-            .positions
-            .iter()
-            .find(|user_position| user_position.get_instrument() == pubkey)
-        {
-            positions.push(p.get_quantity());
-        } else {
-            positions.push(0);
-        }
-    }
-    sol_log_compute_units();
-    //
-
     let asset = margin_stress_account.asset;
-    let now = margin_stress_account.timestamp;
-    let expiry_date = optifi_exchange.get_expiry_date_with_asset(asset);
-
-    let t = expiry_date
-        .iter()
-        .map(|d| (d - now) as f32 / SECS_IN_STANDARD_YEAR as f32)
-        .collect::<Vec<f32>>();
-
-    sol_log_compute_units();
-
-    // 37000 computing units
-    let margin_result = margin_function(
-        positions,
-        &t,
-        &margin_stress_account.option_price,
-        &margin_stress_account.intrinsic_value,
-        &margin_stress_account.option_price_delta_in_stress_price,
-    );
+    let margin_result = update_user_margin(user_account, margin_stress_account);
 
-    let margin_result = margin_result.min(0);
-
-    let amount_to_reserve = -margin_result as u64;
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+    user_account.update_health(user_margin_account);
 
     sol_log_compute_units();
     msg!(
-        "spot_price : {}, iv : {}, amount_to_reserve : {} ",
+        "spot_price : {}, iv : {}, initial_margin : {}, maintanance_margin : {} ",
         u_to_f_repr!(margin_stress_account.spot_price),
         u_to_f_repr!(margin_stress_account.iv),
-        amount_to_reserve
+        user_account.initial_margin[asset as usize],
+        user_account.amount_to_reserve[asset as usize]
     );
+    msg!("margin breakdown : {:?}", margin_result);
 
-    user_account.amount_to_reserve[asset as usize] = amount_to_reserve;
-
-    if !is_margin_sufficient(&user_margin_account, &user_account.amount_to_reserve) {
+    // new orders are accepted against the initial margin, except a reduce-only ask,
+    // which only sells the long tokens the user holds
+    let is_closing_longs = reduce_only && serum_side == Side::Ask;
+    if !is_closing_longs && !is_margin_sufficient(&user_margin_account, user_account) {
         return Err(ErrorCode::InsufficientMargin.into());
     }
 
     // mint the instrument spl token to the seller if it's an ask order
-    if serum_side == Side::Ask {
+    if serum_side == Side::Ask && !reduce_only {
         let instrument_token_mint_authority_pda = &ctx.accounts.instrument_token_mint_authority_pda;
-        let serum_market_account_info = Market::load(serum_market, serum_market.owner)?;
-        let amount_to_mint = max_coin_qty
-            .checked_mul(serum_market_account_info.coin_lot_size)
-            .ok_or(ErrorCode::NumericalOverflowError)? as u64;
+        let amount_to_mint = order_coin_qty;
 
         // mint long token to user
         mint_instrument_token_for_user(
@@ -231,6 +278,8 @@ pub fn handle(
         //     "successfully minted {} spl tokens to the seller spl token account",
         //     amount_to_mint
         // );
+    }
+    if serum_side == Side::Ask {
         order_payer = &ctx.accounts.user_instrument_long_token_vault;
     }
 
@@ -255,6 +304,23 @@ pub fn handle(
     //     "Open orders account owner is {}",
     //     open_orders.owner.to_string()
     // );
+    check_linked_self_trade(
+        ctx.program_id,
+        user_account,
+        serum_market,
+        market_bids,
+        market_asks,
+        serum_side,
+        limit,
+    )?;
+    let payer_amount = accessor::amount(order_payer)?;
+    // the coin or pc deposited into the open orders account by the new order,
+    // so that the taker fill can be told apart from the deposit
+    let balances_before_order =
+        serum_open_orders_balances(serum_market, open_orders, &user_account.to_account_info())?;
+    let orders_before_order =
+        serum_open_orders_count(serum_market, open_orders, &user_account.to_account_info())?;
+
     sol_log_compute_units();
     serum_new_order_with_client_order_id(
         signer_seeds,
@@ -275,39 +341,300 @@ pub fn handle(
         dex_program,
         serum_side,
         limit,
-        max_coin_qty,
-        OrderType::Limit,
+        order_coin_qty / coin_lot_size,
+        order_type.to_serum_order_type(),
         client_order_id,
+        user_account.self_trade_mode.to_serum_self_trade_behavior(),
         max_pc_qty,
         ctx.program_id,
         &optifi_exchange.key(),
+    )?;
+
+    let deposit = payer_amount.saturating_sub(accessor::amount(order_payer)?);
+    let balances_after_order =
+        serum_open_orders_balances(serum_market, open_orders, &user_account.to_account_info())?;
+    let (taker_qty, taker_cost) = taker_fill(
+        serum_side,
+        balances_before_order,
+        balances_after_order,
+        deposit,
+    );
+
+    if !order_type.is_immediate() {
+        // serum drops a post-only order which would cross without an error,
+        // then the tokens minted and the short position added for it would be left behind
+        if order_type == OrderType::PostOnly
+            && serum_open_orders_count(serum_market, open_orders, &user_account.to_account_info())?
+                == orders_before_order
+        {
+            return Err(ErrorCode::PostOnlyOrderNotPosted.into());
+        }
+        user_account.record_open_orders_fills(
+            optifi_market.instrument,
+            balances_before_order,
+            balances_after_order,
+        );
+        user_account.record_taker_fill(optifi_market.instrument, taker_qty, taker_cost);
+        return Ok(());
+    }
+
+    // nothing of an immediate order rests on the orderbook,
+    // the filled coin is what the open orders account gained for a bid and lost for an ask
+    let filled_coin_qty = taker_qty.unsigned_abs();
+    msg!("filled {} of {}", filled_coin_qty, order_coin_qty);
+    if order_type == OrderType::FillOrKill && filled_coin_qty < order_coin_qty {
+        return Err(ErrorCode::OrderNotFilled.into());
+    }
+
+    // settle the fills, and get the unfilled coin of an ask back
+    serum_settle_funds_for_user(
+        signer_seeds[0],
+        dex_program,
+        serum_market,
+        token_program,
+        open_orders,
+        &user_account.to_account_info(),
+        coin_vault,
+        user_instrument_long_token_vault,
+        pc_vault,
+        user_margin_account,
+        &ctx.accounts.vault_signer,
+        &ctx.program_id,
+    )?;
+    let balances_after_settlement =
+        serum_open_orders_balances(serum_market, open_orders, &user_account.to_account_info())?;
+
+    // the tokens minted for the unfilled part of an ask are burnt,
+    // so that only the filled part is kept as short position
+    let unfilled_coin_qty = order_coin_qty.saturating_sub(filled_coin_qty);
+    if serum_side == Side::Ask && !reduce_only && unfilled_coin_qty > 0 {
+        burn_instrument_token_for_user(
+            coin_mint,
+            user_instrument_long_token_vault,
+            user_account.owner,
+            &user_account.to_account_info(),
+            user_account.bump,
+            unfilled_coin_qty,
+            token_program,
+            &optifi_exchange.key(),
+        )?;
+        burn_instrument_token_for_user(
+            instrument_short_spl_token_mint,
+            user_instrument_short_token_vault,
+            user_account.owner,
+            &user_account.to_account_info(),
+            user_account.bump,
+            unfilled_coin_qty,
+            token_program,
+            &optifi_exchange.key(),
+        )?;
+    }
+    let open_bid_qty = serum_open_bid_quantity(
+        serum_market,
+        open_orders,
+        &user_account.to_account_info(),
+        market_bids,
+    )?;
+    let long_amount = accessor::amount(user_instrument_long_token_vault)?;
+    let short_amount = accessor::amount(user_instrument_short_token_vault)?;
+
+    user_account.record_open_orders_fills(
+        optifi_market.instrument,
+        balances_before_order,
+        balances_after_settlement,
+    );
+    user_account.record_taker_fill(optifi_market.instrument, taker_qty, taker_cost);
+    user_account.update_long_position(optifi_market.instrument, long_amount);
+    user_account.update_short_position(optifi_market.instrument, short_amount);
+    user_account.update_open_bid_quantity(optifi_market.instrument, open_bid_qty);
+
+    // the margin is reserved for what is filled only
+    update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+    user_account.update_health(user_margin_account);
+    if !is_closing_longs && !is_margin_sufficient(&user_margin_account, user_account) {
+        return Err(ErrorCode::InsufficientMargin.into());
+    }
+
+    Ok(())
+}
+
+/// Place a market order, an immediate-or-cancel order limited at the theoretical option price
+/// of the margin stress account, moved by at most `max_slippage_bps` against the user
+pub fn handle_market_order(
+    ctx: Context<PlaceOrderContext>,
+    side: OrderSide,
+    max_coin_qty: u64,
+    max_slippage_bps: u16,
+    client_order_id: u64,
+    reduce_only: bool,
+) -> ProgramResult {
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    let instrument = ctx.accounts.optifi_market.instrument;
+    let option_price = margin_stress_account
+        .instruments
+        .iter()
+        .position(|i| i == &instrument)
+        .map(|index| margin_stress_account.option_price[index])
+        .ok_or(ErrorCode::WrongAsset)?;
+
+    let max_slippage_bps = max_slippage_bps as u64;
+    if option_price == 0
+        || max_slippage_bps > ctx.accounts.optifi_exchange.market_order_price_band_bps
+    {
+        return Err(ErrorCode::MarketOrderOutOfBand.into());
+    }
+
+    // native pc per native coin, converted to pc lots per coin lot for serum,
+    // rounded in favour of the user so that the slippage is never exceeded
+    let market = Market::load(&ctx.accounts.serum_market, ctx.accounts.serum_market.owner)?;
+    let slippage = match side {
+        OrderSide::Bid => 10000 + max_slippage_bps,
+        OrderSide::Ask => 10000 - max_slippage_bps,
+    };
+    let numerator = option_price as u128 * slippage as u128 * market.coin_lot_size as u128;
+    let denominator = 10000 * market.pc_lot_size as u128;
+    let limit = match side {
+        OrderSide::Bid => numerator / denominator,
+        OrderSide::Ask => (numerator + denominator - 1) / denominator,
+    } as u64;
+    if limit == 0 {
+        return Err(ErrorCode::MarketOrderOutOfBand.into());
+    }
+
+    let native_pc_qty = limit
+        .checked_mul(market.pc_lot_size)
+        .and_then(|pc| pc.checked_mul(max_coin_qty))
+        .ok_or(ErrorCode::NumericalOverflowError)?;
+    let max_pc_qty = native_pc_qty + native_pc_qty * MARKET_ORDER_FEE_BUFFER_BPS / 10000;
+    drop(market);
+
+    msg!(
+        "market order at {}, option price {}, max slippage {} bps",
+        limit,
+        option_price,
+        max_slippage_bps
+    );
+
+    handle(
+        ctx,
+        side,
+        limit,
+        max_coin_qty,
+        max_pc_qty,
+        client_order_id,
+        OrderType::ImmediateOrCancel,
+        reduce_only,
     )
 }
 
-fn is_margin_sufficient(user_margin_account: &AccountInfo, amount_to_reserve: &[u64]) -> bool {
+#[derive(Accounts)]
+pub struct SetMarketOrderPriceBand<'info> {
+    /// optifi exchange account
+    #[account(mut, constraint = optifi_exchange.exchange_authority == authority.key() @ ErrorCode::UnauthorizedAccount)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// optifi exchange's authority
+    #[account(signer)]
+    pub authority: AccountInfo<'info>,
+}
+
+/// Set the max distance of a market order's limit price from the theoretical option price
+pub fn set_market_order_price_band_handler(
+    ctx: Context<SetMarketOrderPriceBand>,
+    band_bps: u64,
+) -> ProgramResult {
+    // an ask is limited at (10000 - band_bps) bps of the option price
+    if band_bps >= 10_000 {
+        return Err(ErrorCode::InvalidMarketOrderPriceBand.into());
+    }
+    ctx.accounts.optifi_exchange.market_order_price_band_bps = band_bps;
+    msg!("market order price band is set to {} bps", band_bps);
+    Ok(())
+}
+
+/// whether the margin account covers the initial margin and the fees
+/// which haven't been collected yet, as for a withdrawal
+pub fn is_margin_sufficient(user_margin_account: &AccountInfo, user_account: &UserAccount) -> bool {
     let margin = accessor::amount(user_margin_account).unwrap();
-    let maintenance = amount_to_reserve.iter().sum::<u64>();
-    msg!("margin: {}, maintenance: {}", margin, maintenance);
-    if margin >= maintenance {
+    let initial = user_account.get_initial_margin();
+    msg!(
+        "margin: {}, initial margin: {}, cross margin credit: {}, unpaid fees: {}",
+        margin,
+        initial,
+        user_account.cross_margin_credit,
+        user_account.unpaid_fees
+    );
+    if margin >= initial + user_account.unpaid_fees {
         return true;
     }
     return false;
 }
 
-fn pay_order_fees(ctx: &Context<PlaceOrderContext>, notional: u64) -> ProgramResult {
-    let exchange_key = &ctx.accounts.optifi_exchange.key();
-    let (_pda, bump) = get_central_usdc_pool_auth_pda(&exchange_key.clone(), ctx.program_id);
-    let seeds: &[&[&[u8]]] = &[&[
-        PREFIX_CENTRAL_USDC_POOL_AUTH.as_bytes(),
-        exchange_key.as_ref(),
-        &[bump],
-    ]];
-    pay_fees(
-        notional,
-        ctx.accounts.token_program.clone(),
-        ctx.accounts.user_margin_account.clone(),
-        ctx.accounts.user.clone(),
-        ctx.accounts.usdc_central_pool.clone(),
-        Some(seeds),
-    )
+/// the quantity and cost a new order filled as taker, from the native balances of the serum
+/// open orders account before and after the order and the deposit of the order,
+/// the quantity and cost of an ask are negative
+pub fn taker_fill(
+    side: Side,
+    balances_before_order: OpenOrdersBalances,
+    balances_after_order: OpenOrdersBalances,
+    deposit: u64,
+) -> (i64, i64) {
+    let (before, after) = (balances_before_order, balances_after_order);
+    match side {
+        Side::Bid => (
+            after.coin_total.saturating_sub(before.coin_total) as i64,
+            (before.pc_total + deposit).saturating_sub(after.pc_total) as i64,
+        ),
+        Side::Ask => (
+            -((before.coin_total + deposit).saturating_sub(after.coin_total) as i64),
+            -(after.pc_total.saturating_sub(before.pc_total) as i64),
+        ),
+    }
+}
+
+/// Serum only prevents self trades within one open orders account, so an order which would
+/// match a resting order of an owner linked to the user aborts the transaction in any
+/// self trade mode, as the linked owner's orders can't be decremented or cancelled by the user
+pub fn check_linked_self_trade<'info>(
+    program_id: &Pubkey,
+    user_account: &UserAccount,
+    serum_market: &AccountInfo<'info>,
+    market_bids: &AccountInfo<'info>,
+    market_asks: &AccountInfo<'info>,
+    side: Side,
+    limit: u64,
+) -> ProgramResult {
+    if user_account
+        .linked_owners
+        .iter()
+        .all(|owner| *owner == Pubkey::default())
+    {
+        return Ok(());
+    }
+    // the open orders accounts of the linked owners are only derived
+    // once the order would match a resting order
+    let mut linked_open_orders = None;
+    if serum_crosses_open_orders(
+        serum_market,
+        market_bids,
+        market_asks,
+        side,
+        limit,
+        |open_orders| {
+            linked_open_orders
+                .get_or_insert_with(|| {
+                    user_account.get_linked_open_orders(serum_market.key, program_id)
+                })
+                .contains(open_orders)
+        },
+    )? {
+        return Err(ErrorCode::SelfTradeNotAllowed.into());
+    }
+    Ok(())
 }
@@ -0,0 +1,371 @@
+use crate::errors::ErrorCode;
+use crate::financial::margin::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
+use crate::instrument_spl_token_utils::{
+    burn_instrument_token_for_user, mint_instrument_token_for_user,
+};
+use crate::serum_utils::{
+    serum_cancel_order_with_client_order_id, serum_new_order_with_client_order_id,
+    serum_open_bid_quantity, serum_open_orders_balances, serum_open_orders_count,
+    serum_settle_funds_for_user,
+};
+use crate::state::OrderMetadata;
+use crate::utils::{get_serum_market_auth_pda, PREFIX_SERUM_MARKET_AUTH, PREFIX_USER_ACCOUNT};
+use crate::{OrderSide, OrderType};
+use anchor_lang::prelude::*;
+use anchor_spl::token::accessor;
+use serum_dex::critbit::SlabView;
+use serum_dex::error::DexErrorCode;
+use serum_dex::matching::Side;
+use serum_dex::state::Market;
+
+use super::{
+    check_linked_self_trade, close_order_metadata_accounts, find_order_metadata_account,
+    is_margin_sufficient, order_ids_of_client_order_id, split_order_metadata_accounts, taker_fill,
+    PlaceOrderContext,
+};
+
+/// Cancel the resting order of `client_order_id` and place a new order on the same market.
+///
+/// The coin of a cancelled ask is reused by a new ask, so only the difference of the
+/// instrument tokens is minted or burnt, and the margin is calculated once for the net change.
+///
+/// The expiry of a good-til-time order is carried over to the new order, which must use
+/// the same client order id, as the order metadata address is derived from it.
+pub fn handle(
+    ctx: Context<PlaceOrderContext>,
+    client_order_id: u64,
+    side: OrderSide,
+    limit: u64,
+    max_coin_qty: u64,
+    max_pc_qty: u64,
+    new_client_order_id: u64,
+    order_type: OrderType,
+) -> ProgramResult {
+    if order_type.is_immediate() {
+        return Err(ErrorCode::InvalidOrderType.into());
+    }
+
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    let optifi_market = &ctx.accounts.optifi_market;
+    let user_account = &mut ctx.accounts.user_account;
+    let user_margin_account = &ctx.accounts.user_margin_account;
+    let serum_market = &ctx.accounts.serum_market;
+    let open_orders = &ctx.accounts.open_orders;
+    let market_bids = &ctx.accounts.bids;
+    let market_asks = &ctx.accounts.asks;
+    let coin_mint = &ctx.accounts.coin_mint;
+    let token_program = &ctx.accounts.token_program;
+    let dex_program = &ctx.accounts.serum_dex_program_id;
+    let user_instrument_long_token_vault = &ctx.accounts.user_instrument_long_token_vault;
+    let user_instrument_short_token_vault = &ctx.accounts.user_instrument_short_token_vault;
+    let instrument_short_spl_token_mint = &ctx.accounts.instrument_short_spl_token_mint;
+
+    let now = Clock::get()?.unix_timestamp as u64;
+    margin_stress_account.check_available(
+        now,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
+    if user_account.is_in_liquidation {
+        return Err(ErrorCode::CannotPlaceOrdersInLiquidation.into());
+    }
+    if optifi_exchange.get_instrument_asset(&optifi_market.instrument)
+        != Some(margin_stress_account.asset)
+    {
+        return Err(ErrorCode::WrongAsset.into());
+    }
+
+    let exchange_key = optifi_exchange.key();
+    let user_key = user_account.owner;
+    let user_bump = user_account.bump;
+    let user_account_info = user_account.to_account_info();
+    let (_market_auth, bump) = get_serum_market_auth_pda(&exchange_key, ctx.program_id);
+    let signer_seeds: &[&[&[u8]]] = &[
+        &[
+            PREFIX_USER_ACCOUNT.as_bytes(),
+            exchange_key.as_ref(),
+            user_key.as_ref(),
+            &[user_bump],
+        ],
+        &[
+            PREFIX_SERUM_MARKET_AUTH.as_bytes(),
+            exchange_key.as_ref(),
+            &[bump],
+        ],
+    ];
+
+    // the side and serum order id of the resting order, and its coin quantity if it's an ask
+    let (coin_lot_size, old_side, old_order_id, old_ask_coin_qty) = {
+        let market = Market::load(serum_market, dex_program.key)?;
+        let orders = market.load_orders_mut(
+            open_orders,
+            Some(&user_account_info),
+            dex_program.key,
+            None,
+            None,
+        )?;
+        let slot = orders
+            .client_order_ids
+            .iter()
+            .position(|&id| id == client_order_id)
+            .ok_or(DexErrorCode::OrderNotFound)?;
+        let old_side = orders
+            .slot_side(slot as u8)
+            .ok_or(DexErrorCode::OrderNotFound)?;
+        let old_ask_coin_qty = match old_side {
+            Side::Ask => {
+                let asks = market.load_asks_mut(market_asks)?;
+                asks.find_by_key(orders.orders[slot])
+                    .and_then(|handle| asks.get(handle))
+                    .and_then(|node| node.as_leaf())
+                    .map_or(0, |node| node.quantity() * market.coin_lot_size)
+            }
+            Side::Bid => 0,
+        };
+        (
+            market.coin_lot_size,
+            old_side,
+            orders.orders[slot],
+            old_ask_coin_qty,
+        )
+    };
+
+    let (order_metadata_accounts, stress_accounts) =
+        split_order_metadata_accounts(ctx.program_id, ctx.remaining_accounts);
+    let old_order_metadata = find_order_metadata_account(
+        &optifi_market.key(),
+        &user_account_info.key(),
+        &order_metadata_accounts,
+        old_order_id,
+    )?;
+    if old_order_metadata.is_some() && new_client_order_id != client_order_id {
+        return Err(ErrorCode::OrderExpiryNotCarriedOver.into());
+    }
+
+    // the maker fills since the last operation are recorded from the balances before the cancel
+    let balances_before_cancel =
+        serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+    serum_cancel_order_with_client_order_id(
+        signer_seeds[0],
+        dex_program,
+        serum_market,
+        market_bids,
+        market_asks,
+        open_orders,
+        &user_account_info,
+        &ctx.accounts.event_queue,
+        old_side,
+        client_order_id,
+    )?;
+
+    // serum uses the free coin and pc of the open orders account first,
+    // so a new ask only needs the tokens the cancelled ask didn't release
+    let new_ask_coin_qty = match side {
+        OrderSide::Ask => max_coin_qty
+            .checked_mul(coin_lot_size)
+            .ok_or(ErrorCode::NumericalOverflowError)?,
+        OrderSide::Bid => 0,
+    };
+    let amount_to_mint = new_ask_coin_qty.saturating_sub(old_ask_coin_qty);
+    let amount_to_burn = old_ask_coin_qty.saturating_sub(new_ask_coin_qty);
+    if amount_to_mint > 0 {
+        let mint_authority = &ctx.accounts.instrument_token_mint_authority_pda;
+        mint_instrument_token_for_user(
+            coin_mint,
+            user_instrument_long_token_vault,
+            amount_to_mint,
+            token_program,
+            ctx.program_id,
+            &exchange_key,
+            mint_authority,
+        )?;
+        mint_instrument_token_for_user(
+            instrument_short_spl_token_mint,
+            user_instrument_short_token_vault,
+            amount_to_mint,
+            token_program,
+            ctx.program_id,
+            &exchange_key,
+            mint_authority,
+        )?;
+    }
+
+    let (serum_side, order_payer) = match side {
+        OrderSide::Bid => (Side::Bid, user_margin_account),
+        OrderSide::Ask => (Side::Ask, user_instrument_long_token_vault),
+    };
+    check_linked_self_trade(
+        ctx.program_id,
+        user_account,
+        serum_market,
+        market_bids,
+        market_asks,
+        serum_side,
+        limit,
+    )?;
+    let payer_amount = accessor::amount(order_payer)?;
+    let balances_before_order =
+        serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+    let orders_before_order =
+        serum_open_orders_count(serum_market, open_orders, &user_account_info)?;
+    let order_ids_before_order = order_ids_of_client_order_id(
+        serum_market,
+        open_orders,
+        &user_account_info,
+        new_client_order_id,
+    )?;
+    serum_new_order_with_client_order_id(
+        signer_seeds,
+        serum_market,
+        open_orders,
+        &ctx.accounts.request_queue,
+        &ctx.accounts.event_queue,
+        market_bids,
+        market_asks,
+        order_payer,
+        &user_account_info,
+        &ctx.accounts.coin_vault,
+        &ctx.accounts.pc_vault,
+        token_program,
+        &ctx.accounts.rent.to_account_info(),
+        dex_program,
+        serum_side,
+        limit,
+        max_coin_qty,
+        order_type.to_serum_order_type(),
+        new_client_order_id,
+        user_account.self_trade_mode.to_serum_self_trade_behavior(),
+        max_pc_qty,
+        ctx.program_id,
+        &exchange_key,
+    )?;
+    // serum drops a post-only order which would cross without an error
+    if order_type == OrderType::PostOnly
+        && serum_open_orders_count(serum_market, open_orders, &user_account_info)?
+            == orders_before_order
+    {
+        return Err(ErrorCode::PostOnlyOrderNotPosted.into());
+    }
+    let deposit = payer_amount.saturating_sub(accessor::amount(order_payer)?);
+    let mut balances_after_operation =
+        serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+    let (taker_qty, taker_cost) = taker_fill(
+        serum_side,
+        balances_before_order,
+        balances_after_operation,
+        deposit,
+    );
+
+    // the coin of the cancelled ask which isn't used by the new order is settled and burnt
+    if amount_to_burn > 0 {
+        serum_settle_funds_for_user(
+            signer_seeds[0],
+            dex_program,
+            serum_market,
+            token_program,
+            open_orders,
+            &user_account_info,
+            &ctx.accounts.coin_vault,
+            user_instrument_long_token_vault,
+            &ctx.accounts.pc_vault,
+            user_margin_account,
+            &ctx.accounts.vault_signer,
+            ctx.program_id,
+        )?;
+        balances_after_operation =
+            serum_open_orders_balances(serum_market, open_orders, &user_account_info)?;
+
+        let amount_to_burn = amount_to_burn
+            .min(accessor::amount(user_instrument_long_token_vault)?)
+            .min(accessor::amount(user_instrument_short_token_vault)?);
+        burn_instrument_token_for_user(
+            coin_mint,
+            user_instrument_long_token_vault,
+            user_key,
+            &user_account_info,
+            user_bump,
+            amount_to_burn,
+            token_program,
+            &exchange_key,
+        )?;
+        burn_instrument_token_for_user(
+            instrument_short_spl_token_mint,
+            user_instrument_short_token_vault,
+            user_key,
+            &user_account_info,
+            user_bump,
+            amount_to_burn,
+            token_program,
+            &exchange_key,
+        )?;
+    }
+
+    // the new order takes over the expiry of the replaced one, the metadata of the orders
+    // which don't rest on the orderbook anymore is closed
+    if let Some(order_metadata_account) = old_order_metadata {
+        let new_order_id = order_ids_of_client_order_id(
+            serum_market,
+            open_orders,
+            &user_account_info,
+            new_client_order_id,
+        )?
+        .into_iter()
+        .find(|order_id| !order_ids_before_order.contains(order_id));
+        if let Some(new_order_id) = new_order_id {
+            let mut order_metadata =
+                OrderMetadata::try_deserialize(&mut &order_metadata_account.data.borrow()[..])?;
+            order_metadata.order_id = new_order_id;
+            order_metadata.try_serialize(&mut &mut order_metadata_account.data.borrow_mut()[..])?;
+            msg!(
+                "order {} takes over the expiry of order {}",
+                new_order_id,
+                old_order_id
+            );
+        }
+    }
+    close_order_metadata_accounts(
+        &optifi_market.key(),
+        serum_market,
+        open_orders,
+        &user_account_info,
+        &ctx.accounts.user,
+        &order_metadata_accounts,
+    )?;
+
+    user_account.record_open_orders_fills(
+        optifi_market.instrument,
+        balances_before_cancel,
+        balances_after_operation,
+    );
+    user_account.record_taker_fill(optifi_market.instrument, taker_qty, taker_cost);
+    let open_bid_qty =
+        serum_open_bid_quantity(serum_market, open_orders, &user_account_info, market_bids)?;
+    user_account.update_long_position(
+        optifi_market.instrument,
+        accessor::amount(user_instrument_long_token_vault)?,
+    );
+    user_account.update_short_position(
+        optifi_market.instrument,
+        accessor::amount(user_instrument_short_token_vault)?,
+    );
+    user_account.update_open_bid_quantity(optifi_market.instrument, open_bid_qty);
+
+    // the margin is calculated once, for the net change of the replace
+    let margin_result = update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        &stress_accounts,
+    )?;
+    user_account.update_health(user_margin_account);
+    msg!("margin breakdown : {:?}", margin_result);
+
+    if !is_margin_sufficient(user_margin_account, user_account) {
+        return Err(ErrorCode::InsufficientMargin.into());
+    }
+
+    Ok(())
+}
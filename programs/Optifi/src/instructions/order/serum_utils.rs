@@ -1,10 +1,13 @@
+use crate::state::OpenOrdersBalances;
 use crate::utils::{get_serum_market_auth_pda, PREFIX_SERUM_MARKET_AUTH};
 use anchor_lang::{prelude::*, solana_program::program::invoke_signed};
 use serum_dex::instruction::{
     cancel_order, cancel_order_by_client_order_id, new_order, prune, settle_funds,
     SelfTradeBehavior,
 };
+use serum_dex::critbit::SlabView;
 use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::{Market, OpenOrders};
 use std::num::NonZeroU64;
 
 pub fn serum_new_order<'info>(
@@ -118,7 +121,7 @@ pub fn serum_new_order_with_client_order_id<'info>(
     max_coin_qty: u64,
     order_type: OrderType,
     client_order_id: u64,
-    // self_trade_behavior: SelfTradeBehavior,
+    self_trade_behavior: SelfTradeBehavior,
     // limit: u16,
     max_pc_qty: u64,
     program_id: &Pubkey,
@@ -142,9 +145,9 @@ pub fn serum_new_order_with_client_order_id<'info>(
         side,
         NonZeroU64::new(limit_price).unwrap(),
         NonZeroU64::new(max_coin_qty).unwrap(),
-        OrderType::Limit,
+        order_type,
         client_order_id, // Also not sure about this one, client_order_id - Jet has this as a constant 0, so we'll try that.
-        SelfTradeBehavior::AbortTransaction,
+        self_trade_behavior,
         65535,
         NonZeroU64::new(max_pc_qty).unwrap(),
     )?;
@@ -277,6 +280,37 @@ pub fn serum_settle_funds_for_user<'info>(
     vault_signer: &AccountInfo<'info>,
     program_id: &Pubkey,
     // exchange: &Pubkey,
+) -> ProgramResult {
+    serum_settle_funds_with_referrer_for_user(
+        signers_seeds,
+        dex_program,
+        serum_market,
+        token_program,
+        open_orders_account,
+        open_orders_account_owner,
+        coin_vault,
+        coin_wallet,
+        pc_vault,
+        pc_wallet,
+        vault_signer,
+        None,
+    )
+}
+
+/// settle funds of the user, the serum referral rebates are paid into `referrer_pc_wallet`
+pub fn serum_settle_funds_with_referrer_for_user<'info>(
+    signers_seeds: &[&[u8]],
+    dex_program: &AccountInfo<'info>,
+    serum_market: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    open_orders_account: &AccountInfo<'info>,
+    open_orders_account_owner: &AccountInfo<'info>,
+    coin_vault: &AccountInfo<'info>,
+    coin_wallet: &AccountInfo<'info>,
+    pc_vault: &AccountInfo<'info>,
+    pc_wallet: &AccountInfo<'info>,
+    vault_signer: &AccountInfo<'info>,
+    referrer_pc_wallet: Option<&AccountInfo<'info>>,
 ) -> ProgramResult {
     let settle_funds_ix = settle_funds(
         dex_program.key,
@@ -288,25 +322,29 @@ pub fn serum_settle_funds_for_user<'info>(
         coin_wallet.key,
         pc_vault.key,
         pc_wallet.key,
-        None,
+        referrer_pc_wallet.map(|wallet| wallet.key),
         vault_signer.key,
     )?;
     // msg!("Created settle funds instruction");
 
+    let mut account_infos = vec![
+        dex_program.clone(),
+        serum_market.clone(),
+        token_program.clone(),
+        open_orders_account.clone(),
+        open_orders_account_owner.clone(),
+        coin_vault.clone(),
+        coin_wallet.clone(),
+        pc_vault.clone(),
+        pc_wallet.clone(),
+        vault_signer.clone(),
+    ];
+    if let Some(wallet) = referrer_pc_wallet {
+        account_infos.push(wallet.clone());
+    }
     invoke_signed(
         &settle_funds_ix,
-        &[
-            dex_program.clone(),
-            serum_market.clone(),
-            token_program.clone(),
-            open_orders_account.clone(),
-            open_orders_account_owner.clone(),
-            coin_vault.clone(),
-            coin_wallet.clone(),
-            pc_vault.clone(),
-            pc_wallet.clone(),
-            vault_signer.clone(),
-        ],
+        &account_infos,
         // &[&[
         //     PREFIX_USER_ACCOUNT.as_bytes(),
         //     exchange.key().as_ref(),
@@ -371,3 +409,162 @@ pub fn serum_prune_orders_for_user<'info>(
         ]],
     )
 }
+
+/// Get the open long exposure of the open orders account in native coin amount,
+/// which is the quantity of the resting bids plus the free coin which isn't settled yet.
+/// A bid can be filled at any time, so it's counted as a potential long position.
+pub fn serum_open_bid_quantity<'info>(
+    serum_market: &AccountInfo<'info>,
+    open_orders_account: &AccountInfo<'info>,
+    open_orders_account_owner: &AccountInfo<'info>,
+    market_bids: &AccountInfo<'info>,
+) -> Result<u64, ProgramError> {
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let open_orders = market.load_orders_mut(
+        open_orders_account,
+        Some(open_orders_account_owner),
+        serum_market.owner,
+        None,
+        None,
+    )?;
+    let bids = market.load_bids_mut(market_bids)?;
+
+    let mut bid_lots: u64 = 0;
+    for slot in iter_filled_slots(*open_orders) {
+        if open_orders.slot_side(slot) != Some(Side::Bid) {
+            continue;
+        }
+        let order_id = open_orders.orders[slot as usize];
+        if let Some(node) = bids
+            .find_by_key(order_id)
+            .and_then(|handle| bids.get(handle))
+            .and_then(|node| node.as_leaf())
+        {
+            bid_lots += node.quantity();
+        }
+    }
+
+    Ok(bid_lots * market.coin_lot_size + open_orders.native_coin_free)
+}
+
+/// Whether an order of `side` at `limit_price` would match a resting order
+/// of an open orders account for which `is_linked` is true.
+///
+/// The other side of the orderbook is only scanned when its best price crosses the order,
+/// and `is_linked` is only called for the open orders accounts of the resting orders
+/// the order would match.
+pub fn serum_crosses_open_orders<'info>(
+    serum_market: &AccountInfo<'info>,
+    market_bids: &AccountInfo<'info>,
+    market_asks: &AccountInfo<'info>,
+    side: Side,
+    limit_price: u64,
+    mut is_linked: impl FnMut(&Pubkey) -> bool,
+) -> Result<bool, ProgramError> {
+    let market = Market::load(serum_market, serum_market.owner)?;
+    // the resting orders on the other side of the orderbook
+    let book = match side {
+        Side::Bid => market.load_asks_mut(market_asks)?,
+        Side::Ask => market.load_bids_mut(market_bids)?,
+    };
+    let crosses = |price: u64| match side {
+        Side::Bid => price <= limit_price,
+        Side::Ask => price >= limit_price,
+    };
+
+    let best = match side {
+        Side::Bid => book.find_min(),
+        Side::Ask => book.find_max(),
+    };
+    match best
+        .and_then(|handle| book.get(handle))
+        .and_then(|node| node.as_leaf())
+    {
+        Some(leaf) if crosses(leaf.price().get()) => {}
+        _ => return Ok(false),
+    }
+
+    for handle in 0..book.capacity() as u32 {
+        let leaf = match book.get(handle).and_then(|node| node.as_leaf()) {
+            Some(leaf) => leaf,
+            None => continue,
+        };
+        if !crosses(leaf.price().get()) {
+            continue;
+        }
+        // the owner of a leaf is the open orders account of the order
+        let owner: Vec<u8> = leaf
+            .owner()
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect();
+        if is_linked(&Pubkey::new(&owner)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Get the native coin and pc balances of the open orders account
+pub fn serum_open_orders_balances<'info>(
+    serum_market: &AccountInfo<'info>,
+    open_orders_account: &AccountInfo<'info>,
+    open_orders_account_owner: &AccountInfo<'info>,
+) -> Result<OpenOrdersBalances, ProgramError> {
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let open_orders = market.load_orders_mut(
+        open_orders_account,
+        Some(open_orders_account_owner),
+        serum_market.owner,
+        None,
+        None,
+    )?;
+    Ok(OpenOrdersBalances {
+        coin_free: open_orders.native_coin_free,
+        coin_total: open_orders.native_coin_total,
+        pc_free: open_orders.native_pc_free,
+        pc_total: open_orders.native_pc_total,
+    })
+}
+
+/// Get the number of orders of the open orders account, a new order which rests
+/// on the orderbook takes a slot until it's filled and consumed, or cancelled
+pub fn serum_open_orders_count<'info>(
+    serum_market: &AccountInfo<'info>,
+    open_orders_account: &AccountInfo<'info>,
+    open_orders_account_owner: &AccountInfo<'info>,
+) -> Result<u32, ProgramError> {
+    let market = Market::load(serum_market, serum_market.owner)?;
+    let open_orders = market.load_orders_mut(
+        open_orders_account,
+        Some(open_orders_account_owner),
+        serum_market.owner,
+        None,
+        None,
+    )?;
+    Ok((!open_orders.free_slot_bits).count_ones())
+}
+
+#[inline]
+pub fn iter_filled_slots(open_orders: OpenOrders) -> impl Iterator<Item = u8> {
+    struct Iter {
+        bits: u128,
+    }
+    impl Iterator for Iter {
+        type Item = u8;
+        #[inline(always)]
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.bits == 0 {
+                None
+            } else {
+                let next = self.bits.trailing_zeros();
+                let mask = 1u128 << next;
+                self.bits &= !mask;
+                Some(next as u8)
+            }
+        }
+    }
+    Iter {
+        bits: !open_orders.free_slot_bits,
+    }
+}
@@ -0,0 +1,96 @@
+use crate::errors::ErrorCode;
+use crate::state::{Exchange, ReferrerAccount};
+use crate::utils::PREFIX_REFERRER_ACCOUNT;
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, accessor, Transfer};
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct InitReferrerAccount<'info> {
+    /// optifi exchange account
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// the referrer account to be initialized
+    #[account(init,
+        seeds=[PREFIX_REFERRER_ACCOUNT.as_bytes(), optifi_exchange.key().as_ref(), owner.key().as_ref()],
+        payer=owner,
+        bump=bump,
+        space=8+32+32+32+8+8+8+1
+    )]
+    pub referrer_account: ProgramAccount<'info, ReferrerAccount>,
+    /// usdc token account which the rebates are paid into, its authority must be the referrer account
+    #[account(constraint = accessor::mint(&rebate_vault)? == optifi_exchange.usdc_mint
+        && accessor::authority(&rebate_vault)? == referrer_account.key() @ ErrorCode::InvalidAccount)]
+    pub rebate_vault: AccountInfo<'info>,
+    /// the referrer's wallet
+    #[account(mut, signer)]
+    pub owner: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Initialize a referrer account, which users can link when they initialize their user accounts
+pub fn init_referrer_handler(ctx: Context<InitReferrerAccount>, bump: u8) -> ProgramResult {
+    let referrer_account = &mut ctx.accounts.referrer_account;
+    referrer_account.optifi_exchange = ctx.accounts.optifi_exchange.key();
+    referrer_account.owner = ctx.accounts.owner.key();
+    referrer_account.rebate_vault = ctx.accounts.rebate_vault.key();
+    referrer_account.bump = bump;
+
+    msg!("referrer account initialized successfully");
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRebates<'info> {
+    pub optifi_exchange: AccountInfo<'info>,
+    /// the referrer account
+    #[account(mut, constraint = referrer_account.optifi_exchange == optifi_exchange.key()
+        && referrer_account.owner == owner.key() @ ErrorCode::UnauthorizedAccount)]
+    pub referrer_account: ProgramAccount<'info, ReferrerAccount>,
+    /// the referrer's rebate vault
+    #[account(mut, constraint = referrer_account.rebate_vault == rebate_vault.key() @ ErrorCode::InvalidAccount)]
+    pub rebate_vault: AccountInfo<'info>,
+    /// the referrer's wallet
+    #[account(signer)]
+    pub owner: AccountInfo<'info>,
+    /// the usdc token account which the rebates are claimed to
+    #[account(mut)]
+    pub claim_dest: AccountInfo<'info>,
+    #[account(address = token::ID)]
+    pub token_program: AccountInfo<'info>,
+}
+
+/// Claim all the rebates in the referrer's rebate vault,
+/// including the referral rebates serum pays on settlement
+pub fn claim_rebates_handler(ctx: Context<ClaimReferralRebates>) -> ProgramResult {
+    let referrer_account = &mut ctx.accounts.referrer_account;
+    let rebate_vault = &ctx.accounts.rebate_vault;
+
+    let amount = accessor::amount(rebate_vault)?;
+    if amount == 0 {
+        msg!("no rebates to claim");
+        return Ok(());
+    }
+
+    let optifi_exchange_key = ctx.accounts.optifi_exchange.key();
+    token::transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.clone(),
+            Transfer {
+                from: rebate_vault.clone(),
+                to: ctx.accounts.claim_dest.clone(),
+                authority: referrer_account.to_account_info(),
+            },
+        )
+        .with_signer(&[&[
+            PREFIX_REFERRER_ACCOUNT.as_bytes(),
+            optifi_exchange_key.as_ref(),
+            referrer_account.owner.as_ref(),
+            &[referrer_account.bump],
+        ]]),
+        amount,
+    )?;
+    referrer_account.claimed_rebates += amount;
+
+    msg!("claimed {} usdc of referral rebates", amount);
+    Ok(())
+}
@@ -1,4 +1,6 @@
-use crate::state::{Exchange, UserAccount, UserPosition};
+use crate::financial::{update_user_margin, Asset, MarginFunctionResult};
+use crate::instructions::margin::{load_margin_stress_accounts, update_cross_margin_credit};
+use crate::state::{Exchange, MarginStressAccount, UserAccount, UserPosition};
 use anchor_lang::prelude::*;
 
 #[derive(Accounts)]
@@ -7,6 +9,14 @@ pub struct CleanInstrumentForUser<'info> {
     pub user_account: ProgramAccount<'info, UserAccount>,
 
     pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    /// user's margin account, to refresh the user's health
+    #[account(constraint = user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
+    pub user_margin_account_usdc: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the margin stress accounts of the user's assets into
+    // ctx.remaining_accounts, to recalculate the margin on the remaining positions
+    // ====================================================================
 }
 
 pub fn handle(ctx: Context<CleanInstrumentForUser>) -> ProgramResult {
@@ -25,7 +35,7 @@ pub fn handle(ctx: Context<CleanInstrumentForUser>) -> ProgramResult {
         .filter(|i| i.is_valid(&instruments))
         .collect::<Vec<UserPosition>>();
 
-    let len_2 = user_account.positions.len();
+    let len_2 = user_positions.len();
 
     msg!(
         "Clean {} expired instruments in user positions, remaining {} valid instruments",
@@ -35,5 +45,41 @@ pub fn handle(ctx: Context<CleanInstrumentForUser>) -> ProgramResult {
 
     user_account.positions = user_positions;
 
+    // no margin is needed for an asset without any remaining position
+    for &asset in [Asset::Bitcoin, Asset::Ethereum].iter() {
+        let asset_instruments = optifi_exchange.get_instrument_pubkey(Some(asset));
+        if user_account
+            .get_positions(&asset_instruments)
+            .iter()
+            .all(|&qty| qty == 0)
+        {
+            user_account.set_margin_report(asset, MarginFunctionResult::default());
+            user_account.position_values[asset as usize] = 0;
+        }
+    }
+
+    // recalculate the margin on the remaining positions
+    let stress_accounts = load_margin_stress_accounts(
+        ctx.program_id,
+        optifi_exchange,
+        ctx.remaining_accounts,
+    )?;
+    for stress_account in stress_accounts.iter() {
+        update_user_margin(user_account, stress_account);
+    }
+    update_cross_margin_credit(
+        optifi_exchange,
+        user_account,
+        &stress_accounts.iter().collect::<Vec<&MarginStressAccount>>(),
+    );
+    user_account.update_health(&ctx.accounts.user_margin_account_usdc);
+
+    msg!(
+        "total initial_margin : {}, total maintanance_margin : {}, health_factor : {}",
+        user_account.get_initial_margin(),
+        user_account.get_maintanance_margin(),
+        user_account.health_factor
+    );
+
     Ok(())
 }
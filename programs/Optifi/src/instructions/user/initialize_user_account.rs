@@ -1,6 +1,6 @@
 use crate::errors::ErrorCode;
 use crate::state::user_account::{AccountState, UserAccount};
-use crate::state::{LiquidationState, LiquidationStatus};
+use crate::state::{LiquidationState, LiquidationStatus, ReferrerAccount};
 use crate::utils::{PREFIX_LIQUIDATION_STATE, PREFIX_USER_ACCOUNT};
 use anchor_lang::{prelude::*, AnchorDeserialize};
 use anchor_spl::token::Token;
@@ -23,7 +23,7 @@ pub struct InitializeUserAccount<'info> {
         seeds=[PREFIX_USER_ACCOUNT.as_bytes(), optifi_exchange.key().as_ref(), owner.key().as_ref()],
         payer=owner,
         bump=bump.user_account,
-        space=3200 // 96+16+1+36*48+1+1+80+36*32+36
+        space=7114 // 96+16+1+36*120+1+1+80+80+1+8+960+80+8+8+8+8+32+1+128+36*32+36
     )]
     pub user_account: ProgramAccount<'info, UserAccount>,
 
@@ -55,6 +55,9 @@ pub struct InitializeUserAccount<'info> {
     // #[account(address = system_program::ID)]
     // pub system_program: AccountInfo<'info>,
     pub rent: Sysvar<'info, Rent>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // to link a referrer, pass its referrer account into ctx.remaining_accounts
+    // ====================================================================
 }
 
 pub fn handler(
@@ -78,7 +81,25 @@ pub fn handler(
 
     let user_margin_account_usdc = &ctx.accounts.user_margin_account_usdc;
     user_account.user_margin_account_usdc = user_margin_account_usdc.key();
-    let _optifi_exchange = &ctx.accounts.optifi_exchange;
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+
+    // link the referrer, who receives a share of the user's trading fees
+    if let Some(referrer_account_info) = ctx.remaining_accounts.first() {
+        if referrer_account_info.owner != ctx.program_id || !referrer_account_info.is_writable {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        let mut referrer_account =
+            ReferrerAccount::try_deserialize(&mut &referrer_account_info.data.borrow()[..])?;
+        if referrer_account.optifi_exchange != optifi_exchange.key()
+            || referrer_account.owner == user_account.owner
+        {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        referrer_account.referred_users += 1;
+        referrer_account.try_serialize(&mut &mut referrer_account_info.data.borrow_mut()[..])?;
+        user_account.referrer = referrer_account_info.key();
+        msg!("linked referrer {}", user_account.referrer);
+    }
 
     // Transfer the ownership of user margin account to the user account (pda)
     // Note that the pda controls all spl token vaults of the user
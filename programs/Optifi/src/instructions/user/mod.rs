@@ -1,11 +1,19 @@
 pub mod clean_expired_instruments_for_user;
 pub mod deposit;
 pub mod initialize_user_account;
+pub mod preview_margin;
+pub mod refresh_health;
+pub mod set_cross_margin;
+pub mod set_self_trade_prevention;
 pub mod user_margin;
 pub mod withdraw;
 
 pub use clean_expired_instruments_for_user::*;
 pub use deposit::*;
 pub use initialize_user_account::*;
+pub use preview_margin::*;
+pub use refresh_health::*;
+pub use set_cross_margin::*;
+pub use set_self_trade_prevention::*;
 pub use user_margin::*;
 pub use withdraw::*;
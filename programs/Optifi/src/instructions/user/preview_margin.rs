@@ -0,0 +1,89 @@
+use crate::financial::margin::update_user_margin;
+use crate::financial::{MarginPreview, PositionDelta};
+use crate::instructions::margin::update_cross_margin;
+use crate::state::MarginStressAccount;
+use crate::state::UserAccount;
+use crate::Exchange;
+use anchor_lang::prelude::*;
+use solana_program::program::set_return_data;
+
+#[derive(Accounts, Clone)]
+pub struct PreviewMarginContext<'info> {
+    /// optifi_exchange account
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
+    /// user's optifi account
+    #[account(constraint = user_account.optifi_exchange == optifi_exchange.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // ====================================================================
+}
+
+/// the user's margin before and after the position deltas, set as the return data of preview_margin
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarginPreviewResult {
+    pub before: MarginPreview,
+    pub after: MarginPreview,
+}
+
+/// Preview the user's margin before and after the position deltas, calculated as the orders
+/// are checked, with the cross margin credit. No account is changed, the results are set as
+/// the return data of the transaction. Deltas of instruments which aren't in the margin
+/// stress account are ignored.
+pub fn handle(ctx: Context<PreviewMarginContext>, deltas: Vec<PositionDelta>) -> ProgramResult {
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    margin_stress_account.check_available(
+        Clock::get()?.unix_timestamp as u64,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
+
+    let mut user_account_before: UserAccount = (*ctx.accounts.user_account).clone();
+    let mut user_account_after = user_account_before.clone();
+    for delta in deltas.iter() {
+        if margin_stress_account
+            .instruments
+            .contains(&delta.instrument)
+        {
+            user_account_after.apply_position_delta(delta.instrument, delta.quantity);
+        }
+    }
+
+    let result = MarginPreviewResult {
+        before: preview(&ctx, &mut user_account_before)?,
+        after: preview(&ctx, &mut user_account_after)?,
+    };
+    msg!("margin before: {:?}", result.before);
+    msg!("margin after: {:?}", result.after);
+
+    set_return_data(
+        &result
+            .try_to_vec()
+            .map_err(|e| ProgramError::BorshIoError(e.to_string()))?,
+    );
+
+    Ok(())
+}
+
+/// recalculate the margin of the user account copy, as the orders do before the margin check
+fn preview(
+    ctx: &Context<PreviewMarginContext>,
+    user_account: &mut UserAccount,
+) -> Result<MarginPreview, ProgramError> {
+    let margin = update_user_margin(user_account, &ctx.accounts.margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        &ctx.accounts.optifi_exchange,
+        user_account,
+        &ctx.accounts.margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+    Ok(MarginPreview {
+        margin,
+        cross_margin_credit: user_account.cross_margin_credit,
+        initial_margin: user_account.get_initial_margin(),
+    })
+}
@@ -0,0 +1,50 @@
+use crate::financial::update_user_margin;
+use crate::instructions::margin::{load_margin_stress_accounts, update_cross_margin_credit};
+use crate::state::{Exchange, MarginStressAccount, UserAccount};
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct RefreshHealth<'info> {
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    /// user's optifi account
+    #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+
+    /// user's margin account
+    #[account(constraint = user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
+    pub user_margin_account_usdc: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // pass the margin stress accounts of the user's assets into
+    // ctx.remaining_accounts, the margin of an asset whose margin stress
+    // account isn't passed is kept as it is
+    // ====================================================================
+}
+
+/// Recalculate the user's margin and health, anyone can call it,
+/// e.g. liquidators before checking the health factor of the user
+pub fn handler(ctx: Context<RefreshHealth>) -> ProgramResult {
+    let optifi_exchange = &ctx.accounts.optifi_exchange;
+    let user_account = &mut ctx.accounts.user_account;
+
+    let stress_accounts =
+        load_margin_stress_accounts(ctx.program_id, optifi_exchange, ctx.remaining_accounts)?;
+    for stress_account in stress_accounts.iter() {
+        update_user_margin(user_account, stress_account);
+    }
+    update_cross_margin_credit(
+        optifi_exchange,
+        user_account,
+        &stress_accounts.iter().collect::<Vec<&MarginStressAccount>>(),
+    );
+    user_account.update_health(&ctx.accounts.user_margin_account_usdc);
+
+    msg!(
+        "total maintanance_margin : {}, health_factor : {}, margin_ratio : {}",
+        user_account.get_maintanance_margin(),
+        user_account.health_factor,
+        user_account.margin_ratio
+    );
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::state::user_account::UserAccount;
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct SetCrossMargin<'info> {
+    /// user's optifi account
+    #[account(mut, constraint = user_account.owner == user.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    /// the user's wallet
+    #[account(signer)]
+    pub user: AccountInfo<'info>,
+}
+
+/// Turn the cross margin mode on or off for the user,
+/// the credit is only given after the margin is calculated again
+pub fn handler(ctx: Context<SetCrossMargin>, enabled: bool) -> ProgramResult {
+    let user_account = &mut ctx.accounts.user_account;
+    user_account.cross_margin = enabled;
+    user_account.cross_margin_credit = 0;
+
+    msg!("cross margin enabled: {}", enabled);
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use crate::constants::{MAX_AMM_LINKED_USERS, MAX_LINKED_OWNERS};
+use crate::errors::ErrorCode;
+use crate::financial::SelfTradeMode;
+use crate::state::user_account::UserAccount;
+use crate::state::Exchange;
+use crate::utils::get_amm_liquidity_auth_pda;
+use anchor_lang::prelude::*;
+
+#[derive(Accounts)]
+pub struct SetSelfTradePrevention<'info> {
+    /// optifi_exchange account, which records the users linking the amm
+    #[account(mut)]
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+    /// user's optifi account
+    #[account(mut, constraint = user_account.owner == user.key(),
+        constraint = user_account.optifi_exchange == optifi_exchange.key())]
+    pub user_account: ProgramAccount<'info, UserAccount>,
+    /// the user's wallet
+    #[account(signer)]
+    pub user: AccountInfo<'info>,
+}
+
+/// Set what happens when the user's orders would match each other, and the owners
+/// counted as the same owner as the user: the user accounts in `linked_owners`,
+/// e.g. of a linked market maker, and the amm if `include_amm` is set.
+/// The users linking the amm are recorded on the exchange, to be checked by the amm order updates
+pub fn handler(
+    ctx: Context<SetSelfTradePrevention>,
+    mode: SelfTradeMode,
+    linked_owners: Vec<Pubkey>,
+    include_amm: bool,
+) -> ProgramResult {
+    let optifi_exchange = &mut ctx.accounts.optifi_exchange;
+    let user_account = &mut ctx.accounts.user_account;
+    let user_account_key = user_account.key();
+
+    let mut owners = linked_owners;
+    if include_amm {
+        owners.push(get_amm_liquidity_auth_pda(&user_account.optifi_exchange, ctx.program_id).0);
+    }
+    if owners.len() > MAX_LINKED_OWNERS || owners.iter().any(|owner| *owner == user_account.key()) {
+        return Err(ErrorCode::InvalidAccount.into());
+    }
+
+    let linked_users = &mut optifi_exchange.amm_linked_users;
+    linked_users.retain(|user| *user != user_account_key);
+    if include_amm {
+        if linked_users.len() >= MAX_AMM_LINKED_USERS {
+            return Err(ErrorCode::AmmLinkedUsersFull.into());
+        }
+        linked_users.push(user_account_key);
+    }
+
+    user_account.self_trade_mode = mode;
+    user_account.linked_owners = [Pubkey::default(); MAX_LINKED_OWNERS];
+    user_account.linked_owners[..owners.len()].copy_from_slice(&owners);
+
+    msg!(
+        "self trade mode: {:?}, {} linked owners",
+        mode,
+        owners.len()
+    );
+    Ok(())
+}
@@ -1,6 +1,7 @@
-use crate::constants::{SECS_IN_STANDARD_YEAR, USDC_DECIMALS};
+use crate::constants::USDC_DECIMALS;
 
-use crate::financial::margin_function;
+use crate::financial::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
 
 use crate::state::MarginStressAccount;
 use crate::state::UserAccount;
@@ -17,9 +18,16 @@ pub struct MarginContext<'info> {
     /// user's optifi account
     #[account(mut, constraint = user_account.optifi_exchange == optifi_exchange.key())]
     pub user_account: ProgramAccount<'info, UserAccount>,
+    /// user's margin account, to refresh the user's health
+    #[account(constraint = user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
+    pub user_margin_account_usdc: AccountInfo<'info>,
 
     /// Clock to get the timestamp
     pub clock: Sysvar<'info, Clock>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // ====================================================================
 }
 
 pub fn handle(ctx: Context<MarginContext>) -> ProgramResult {
@@ -27,57 +35,95 @@ pub fn handle(ctx: Context<MarginContext>) -> ProgramResult {
     let margin_stress_account = &ctx.accounts.margin_stress_account;
     let user_account = &mut ctx.accounts.user_account;
 
-    // let now = Clock::get().unwrap().unix_timestamp as u64;
+    let now = Clock::get()?.unix_timestamp as u64;
+    margin_stress_account.check_available(
+        now,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
 
     sol_log_compute_units();
     // Margin calculation
-    let mut positions = vec![];
-
-    for pubkey in &margin_stress_account.instruments {
-        if let Some(p) = user_account
-            .positions
-            .iter()
-            .find(|user_position| user_position.get_instrument() == pubkey)
-        {
-            positions.push(p.get_quantity());
-        } else {
-            positions.push(0);
-        }
-    }
-    sol_log_compute_units();
-    //
-
     let asset = margin_stress_account.asset;
-    let now = margin_stress_account.timestamp;
-    let expiry_date = optifi_exchange.get_expiry_date_with_asset(asset);
+    let margin_result = update_user_margin(user_account, margin_stress_account);
 
-    let t = expiry_date
-        .iter()
-        .map(|d| (d - now) as f32 / SECS_IN_STANDARD_YEAR as f32)
-        .collect::<Vec<f32>>();
-
-    sol_log_compute_units();
-
-    let margin_result = margin_function(
-        positions,
-        &t,
-        &margin_stress_account.option_price,
-        &margin_stress_account.intrinsic_value,
-        &margin_stress_account.option_price_delta_in_stress_price,
-    );
-    let margin_result = margin_result.min(0);
-
-    let amount_to_reserve = -margin_result as u64;
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+    user_account.update_health(&ctx.accounts.user_margin_account_usdc);
 
     sol_log_compute_units();
     msg!(
-        "spot_price : {}, iv : {}, amount_to_reserve : {} ",
+        "spot_price : {}, iv : {}, initial_margin : {}, maintanance_margin : {} ",
         i_to_f_repr!(margin_stress_account.spot_price),
         u_to_f_repr!(margin_stress_account.iv),
-        amount_to_reserve
+        user_account.initial_margin[asset as usize],
+        user_account.amount_to_reserve[asset as usize]
+    );
+    msg!("margin breakdown : {:?}", margin_result);
+    msg!(
+        "total initial_margin : {}, total maintanance_margin : {}, cross_margin_credit : {}",
+        user_account.get_initial_margin(),
+        user_account.get_maintanance_margin(),
+        user_account.cross_margin_credit
     );
+    msg!(
+        "health_factor : {}, margin_ratio : {}",
+        user_account.health_factor,
+        user_account.margin_ratio
+    );
+
+    Ok(())
+}
 
-    user_account.amount_to_reserve[asset as usize] = amount_to_reserve;
+/// Recalculate the user's margin after the positions are changed.
+///
+/// The margin is only recalculated when the margin stress is available,
+/// otherwise the last requirement is kept until the margin is calculated again.
+/// The user's health is refreshed from the margin account either way.
+pub fn refresh_user_margin<'info>(
+    program_id: &Pubkey,
+    optifi_exchange: &ProgramAccount<'info, Exchange>,
+    user_account: &mut UserAccount,
+    user_margin_account: &AccountInfo<'info>,
+    margin_stress_account: &MarginStressAccount,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp as u64;
+    if margin_stress_account
+        .check_available(
+            now,
+            optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+        )
+        .is_err()
+    {
+        msg!("margin stress is not available, keep the last margin requirement");
+        user_account.update_health(user_margin_account);
+        return Ok(());
+    }
+
+    let asset = margin_stress_account.asset;
+    update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        remaining_accounts,
+    )?;
+    user_account.update_health(user_margin_account);
+
+    msg!(
+        "initial_margin : {}, maintanance_margin : {}, total initial_margin : {}, total maintanance_margin : {}, health_factor : {}",
+        user_account.initial_margin[asset as usize],
+        user_account.amount_to_reserve[asset as usize],
+        user_account.get_initial_margin(),
+        user_account.get_maintanance_margin(),
+        user_account.health_factor
+    );
 
     Ok(())
 }
@@ -1,5 +1,8 @@
 use crate::errors::ErrorCode;
+use crate::financial::margin::update_user_margin;
+use crate::instructions::margin::update_cross_margin;
 use crate::state::user_account::UserAccount;
+use crate::state::{Exchange, MarginStressAccount};
 use crate::utils::PREFIX_USER_ACCOUNT;
 use anchor_lang::prelude::*;
 use anchor_lang::Key;
@@ -12,10 +15,15 @@ use solana_program::program::invoke_signed;
 #[derive(Accounts)]
 // #[instruction(bump: u8)]
 pub struct Withdraw<'info> {
-    pub optifi_exchange: AccountInfo<'info>,
+    pub optifi_exchange: ProgramAccount<'info, Exchange>,
+
+    /// margin stress account of one of the user's assets, the user's margin of the asset
+    /// is recalculated with it before the withdrawal is checked
+    #[account(constraint = margin_stress_account.optifi_exchange == optifi_exchange.key())]
+    pub margin_stress_account: ProgramAccount<'info, MarginStressAccount>,
 
     /// user account - also the pda that controls the user's spl token accounts
-    #[account(mut, constraint= user_account.owner == user.key() && user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
+    #[account(mut, constraint= user_account.optifi_exchange == optifi_exchange.key() && user_account.owner == user.key() && user_account.user_margin_account_usdc == user_margin_account_usdc.key())]
     pub user_account: Account<'info, UserAccount>,
 
     /// user's margin account whose authority is user account(pda)
@@ -37,6 +45,10 @@ pub struct Withdraw<'info> {
 
     #[account(address = token::ID)]
     pub token_program: AccountInfo<'info>,
+    // =================== !!! IMPORTANT NOTE !!! =========================
+    // in cross margin mode, pass the margin stress accounts of the user's
+    // other assets into ctx.remaining_accounts
+    // ====================================================================
 }
 
 impl<'info> Withdraw<'info> {
@@ -57,7 +69,7 @@ impl<'info> Withdraw<'info> {
 
 /// Withdraw tokens
 pub fn handler(ctx: Context<Withdraw>, amount: u64) -> ProgramResult {
-    let user_account = &ctx.accounts.user_account;
+    let user_account = &mut ctx.accounts.user_account;
     let user_margin_account_usdc = &mut ctx.accounts.user_margin_account_usdc;
 
     if user_account.owner != ctx.accounts.user.key() {
@@ -69,30 +81,57 @@ pub fn handler(ctx: Context<Withdraw>, amount: u64) -> ProgramResult {
     }
     let optifi_exchange = &ctx.accounts.optifi_exchange;
     let user = &ctx.accounts.user;
-    let maintanance_margin = user_account.get_maintanance_margin();
+
+    // prices may have moved since the user's last trade, so the margin requirement
+    // and the cross margin credit are recalculated on the current prices
+    let margin_stress_account = &ctx.accounts.margin_stress_account;
+    margin_stress_account.check_available(
+        Clock::get()?.unix_timestamp as u64,
+        optifi_exchange.instruments_epoch[margin_stress_account.asset as usize],
+    )?;
+    update_user_margin(user_account, margin_stress_account);
+    update_cross_margin(
+        ctx.program_id,
+        optifi_exchange,
+        user_account,
+        margin_stress_account,
+        ctx.remaining_accounts,
+    )?;
+
+    let initial_margin = user_account.get_initial_margin();
     let user_margin = accessor::amount(user_margin_account_usdc).unwrap();
 
+    let unpaid_fees = user_account.unpaid_fees;
+
     msg!(
-        "maintanance_margin({}) + withdraw({}) should be greater than user_margin({})",
-        maintanance_margin,
+        "user_margin({}) should be greater than initial_margin({}) + unpaid_fees({}) + withdraw({})",
         user_margin,
+        initial_margin,
+        unpaid_fees,
         amount
     );
 
-    if user_margin < maintanance_margin + amount {
+    // withdrawals may not take the user below the initial margin, nor leave the fees unpaid
+    if user_margin < initial_margin + unpaid_fees + amount {
         return Err(ErrorCode::InsufficientFund.into());
     }
 
     // Method 1:
+    let user_bump = user_account.bump;
     token::transfer(
         ctx.accounts.transfer_context().with_signer(&[&[
             &PREFIX_USER_ACCOUNT.as_bytes(),
             optifi_exchange.key().as_ref(),
             user.key().as_ref(),
-            &[user_account.bump],
+            &[user_bump],
         ]]),
         amount,
     )?;
 
+    // health of the recalculated margin requirement, with the collateral left
+    ctx.accounts
+        .user_account
+        .update_health(&ctx.accounts.user_margin_account_usdc);
+
     Ok(())
 }
@@ -7,9 +7,10 @@ mod macros;
 pub mod state;
 pub mod utils;
 
-use financial::OrderSide;
+use financial::{OrderSide, OrderType, PositionDelta, SelfTradeMode};
 use instructions::*;
-use state::exchange::Exchange;
+use state::exchange::{Exchange, IvTermOracle};
+use state::risk_parameter::StressScenario;
 
 declare_id!("FVWhLLPYPPPVtmrAwSgsy4cF84z888hamnyXYdtFN2jT");
 
@@ -97,6 +98,8 @@ pub mod optifi_exchange {
         max_coin_qty: u64,
         max_pc_qty: u64,
         client_order_id: u64,
+        order_type: OrderType,
+        reduce_only: bool,
     ) -> ProgramResult {
         instructions::order::place_order::handle(
             ctx,
@@ -105,17 +108,81 @@ pub mod optifi_exchange {
             max_coin_qty,
             max_pc_qty,
             client_order_id,
+            order_type,
+            reduce_only,
         )
     }
 
-    // /// Cancel a previously placed order
-    // pub fn cancel_order(
-    //     ctx: Context<CancelOrderContext>,
-    //     side: OrderSide,
-    //     order_id: u128,
-    // ) -> ProgramResult {
-    //     instructions::order::cancel_order::handle(ctx, side, order_id)
-    // }
+    /// Place a market order with a max slippage from the theoretical option price
+    pub fn place_market_order(
+        ctx: Context<PlaceOrderContext>,
+        side: OrderSide,
+        max_coin_qty: u64,
+        max_slippage_bps: u16,
+        client_order_id: u64,
+        reduce_only: bool,
+    ) -> ProgramResult {
+        instructions::order::place_order::handle_market_order(
+            ctx,
+            side,
+            max_coin_qty,
+            max_slippage_bps,
+            client_order_id,
+            reduce_only,
+        )
+    }
+
+    /// Set the max slippage of market orders from the theoretical option price
+    pub fn set_market_order_price_band(
+        ctx: Context<SetMarketOrderPriceBand>,
+        band_bps: u64,
+    ) -> ProgramResult {
+        instructions::order::place_order::set_market_order_price_band_handler(ctx, band_bps)
+    }
+
+    /// Submit a new order which can be cancelled by anyone after the expiry
+    pub fn place_order_with_expiry(
+        ctx: Context<PlaceOrderWithExpiryContext>,
+        client_order_id: u64,
+        bump: u8,
+        side: OrderSide,
+        limit: u64,
+        max_coin_qty: u64,
+        max_pc_qty: u64,
+        order_type: OrderType,
+        expiry: u64,
+    ) -> ProgramResult {
+        instructions::order::order_expiry::handle_place_order_with_expiry(
+            ctx,
+            client_order_id,
+            bump,
+            side,
+            limit,
+            max_coin_qty,
+            max_pc_qty,
+            order_type,
+            expiry,
+        )
+    }
+
+    /// Cancel an expired order - cranker function
+    pub fn cancel_expired_order(ctx: Context<CancelExpiredOrderContext>) -> ProgramResult {
+        instructions::order::order_expiry::handle_cancel_expired_order(ctx)
+    }
+
+    /// Place the legs of a combo order together, margin is checked on the combined portfolio
+    pub fn place_combo_order(ctx: Context<ComboOrderContext>, legs: Vec<ComboLeg>) -> ProgramResult {
+        instructions::order::combo_order::handle(ctx, legs)
+    }
+
+    /// Cancel a previously placed order by its serum order id
+    pub fn cancel_order(
+        ctx: Context<CancelOrderContext>,
+        side: OrderSide,
+        order_id: u128,
+    ) -> ProgramResult {
+        instructions::order::cancel_order::handle(ctx, side, order_id)
+    }
 
     /// Cancel a previously placed order
     pub fn cancel_order_by_client_order_id(
@@ -126,6 +193,42 @@ pub mod optifi_exchange {
         instructions::order::cancel_order::handle2(ctx, side, client_order_id)
     }
 
+    /// Cancel a resting order by client order id and place a new order on the same market
+    pub fn replace_order(
+        ctx: Context<PlaceOrderContext>,
+        client_order_id: u64,
+        side: OrderSide,
+        limit: u64,
+        max_coin_qty: u64,
+        max_pc_qty: u64,
+        new_client_order_id: u64,
+        order_type: OrderType,
+    ) -> ProgramResult {
+        instructions::order::replace_order::handle(
+            ctx,
+            client_order_id,
+            side,
+            limit,
+            max_coin_qty,
+            max_pc_qty,
+            new_client_order_id,
+            order_type,
+        )
+    }
+
+    /// Cancel all the user's orders on an optifi market
+    pub fn cancel_all_orders(ctx: Context<CancelOrderContext>) -> ProgramResult {
+        instructions::order::cancel_all_orders::handle_cancel_all(ctx)
+    }
+
+    /// Cancel all the user's orders on several optifi markets
+    pub fn cancel_all_orders_batch(
+        ctx: Context<CancelAllOrdersContext>,
+        markets: u8,
+    ) -> ProgramResult {
+        instructions::order::cancel_all_orders::handle_cancel_all_batch(ctx, markets)
+    }
+
     /// Initialize user's optifi account
     pub fn init_user_account(
         ctx: Context<InitializeUserAccount>,
@@ -150,6 +253,39 @@ pub mod optifi_exchange {
         instructions::user::user_margin::handle(ctx)
     }
 
+    /// Preview user's margin before and after hypothetical position changes
+    pub fn preview_margin(
+        ctx: Context<PreviewMarginContext>,
+        deltas: Vec<PositionDelta>,
+    ) -> ProgramResult {
+        instructions::user::preview_margin::handle(ctx, deltas)
+    }
+
+    /// Recalculate user's margin, health factor and margin ratio
+    pub fn refresh_health(ctx: Context<RefreshHealth>) -> ProgramResult {
+        instructions::user::refresh_health::handler(ctx)
+    }
+
+    /// Turn the cross margin mode on or off for the user
+    pub fn set_cross_margin(ctx: Context<SetCrossMargin>, enabled: bool) -> ProgramResult {
+        instructions::user::set_cross_margin::handler(ctx, enabled)
+    }
+
+    /// Set the self trade prevention mode of the user and the owners linked to the user
+    pub fn set_self_trade_prevention(
+        ctx: Context<SetSelfTradePrevention>,
+        mode: SelfTradeMode,
+        linked_owners: Vec<Pubkey>,
+        include_amm: bool,
+    ) -> ProgramResult {
+        instructions::user::set_self_trade_prevention::handler(
+            ctx,
+            mode,
+            linked_owners,
+            include_amm,
+        )
+    }
+
     /// Fund settlement - cranker function
     /// Record pnl for one user on one optifi market(one instruments)
     pub fn record_pnl_for_one_user(ctx: Context<RecordPnLForOneUser>) -> ProgramResult {
@@ -293,4 +429,87 @@ pub mod optifi_exchange {
     pub fn margin_stress_calculate(ctx: Context<CalculateMarginStressContext>) -> ProgramResult {
         instructions::margin::calculate::handle(ctx)
     }
+
+    /// Rebuild a margin stress account's instruments from the exchange,
+    /// it's moved into a larger account when they don't fit in it
+    pub fn margin_stress_resync(ctx: Context<ResyncMarginStressContext>) -> ProgramResult {
+        instructions::margin::resync::resync_handler(ctx)
+    }
+
+    /// Set the iv oracles by tenor for the iv term structure of an asset
+    pub fn set_iv_term_oracles(
+        ctx: Context<UpdateIvTermOracles>,
+        asset: u8,
+        iv_term_oracles: Vec<IvTermOracle>,
+    ) -> ProgramResult {
+        instructions::oracle_config::set_iv_term_oracles_handler(ctx, asset, iv_term_oracles)
+    }
+
+    /// Initialize the risk parameters of an asset
+    pub fn init_risk_parameter(
+        ctx: Context<InitRiskParameterContext>,
+        bump: u8,
+        asset: u8,
+    ) -> ProgramResult {
+        instructions::margin::risk_parameter::init_handler(ctx, bump, asset)
+    }
+
+    /// Set the stress scenarios of an asset for the margin calculation
+    pub fn set_stress_scenarios(
+        ctx: Context<SetStressScenariosContext>,
+        scenarios: Vec<StressScenario>,
+    ) -> ProgramResult {
+        instructions::margin::risk_parameter::set_stress_scenarios_handler(ctx, scenarios)
+    }
+
+    /// Set the protocol fee vault which the trading fees are collected into
+    pub fn set_fee_vault(ctx: Context<SetFeeVault>) -> ProgramResult {
+        instructions::fees::set_fee_vault_handler(ctx)
+    }
+
+    /// Set the share of the trading fees paid to the referrers
+    pub fn set_referral_fee_share(
+        ctx: Context<SetReferralFeeShare>,
+        share_bps: u64,
+    ) -> ProgramResult {
+        instructions::fees::set_referral_fee_share_handler(ctx, share_bps)
+    }
+
+    /// Initialize a referrer account that users can link at init_user_account
+    pub fn init_referrer_account(ctx: Context<InitReferrerAccount>, bump: u8) -> ProgramResult {
+        instructions::referral::init_referrer_handler(ctx, bump)
+    }
+
+    /// Claim the referral rebates of a referrer
+    pub fn claim_referral_rebates(ctx: Context<ClaimReferralRebates>) -> ProgramResult {
+        instructions::referral::claim_rebates_handler(ctx)
+    }
+
+    /// Set the spot correlation between two assets for cross margin
+    pub fn set_asset_correlation(
+        ctx: Context<SetAssetCorrelation>,
+        asset_a: u8,
+        asset_b: u8,
+        correlation: i64,
+    ) -> ProgramResult {
+        instructions::margin::cross_margin::set_asset_correlation_handler(
+            ctx,
+            asset_a,
+            asset_b,
+            correlation,
+        )
+    }
+
+    /// Initialize a mock oracle feed - only available with the `testing` feature
+    pub fn init_mock_oracle(
+        ctx: Context<InitializeMockOracle>,
+        data: MockOracleData,
+    ) -> ProgramResult {
+        instructions::mock_oracle::init_handler(ctx, data)
+    }
+
+    /// Set the price, iv and timestamp of a mock oracle feed - only available with the `testing` feature
+    pub fn set_mock_oracle(ctx: Context<SetMockOracle>, data: MockOracleData) -> ProgramResult {
+        instructions::mock_oracle::set_handler(ctx, data)
+    }
 }
@@ -1,5 +1,6 @@
 use crate::financial::instruments::*;
 use crate::financial::*;
+use crate::i_to_f_repr;
 use anchor_lang::prelude::*;
 use solana_program::pubkey::Pubkey;
 
@@ -25,9 +26,42 @@ pub struct Exchange {
     pub instrument_common: Vec<InstrumentCommon>,
     // a list of all created instruments, it should be updated when new instrument is created
     pub instrument_unique: Vec<Vec<InstrumentUnique>>,
+    /// spot correlations between assets, used to give cross margin credit
+    pub asset_correlations: Vec<AssetCorrelation>,
+    /// version of the instrument list of each asset,
+    /// it's increased when instruments of the asset are created or cleaned
+    pub instruments_epoch: [u64; 10],
+    /// protocol fee vault which the trading fees are collected into, a usdc token account
+    pub fee_vault: Pubkey,
+    /// share of the trading fees paid to the referrer of the user, in bps
+    pub referral_fee_share_bps: u64,
+    /// max distance of a market order's limit price from the theoretical option price, in bps
+    pub market_order_price_band_bps: u64,
+    /// user accounts which count the amm as the same owner for self trade prevention
+    pub amm_linked_users: Vec<Pubkey>,
 }
 
 impl Exchange {
+    /// get the configured spot correlation between two assets
+    pub fn get_asset_correlation(&self, asset_a: Asset, asset_b: Asset) -> Option<f32> {
+        self.asset_correlations
+            .iter()
+            .find(|c| {
+                (c.asset_a == asset_a && c.asset_b == asset_b)
+                    || (c.asset_a == asset_b && c.asset_b == asset_a)
+            })
+            .map(|c| i_to_f_repr!(c.correlation))
+    }
+
+    /// get the iv term structure oracles of the asset, sorted by tenor
+    pub fn get_iv_term_oracles(&self, asset: Asset) -> Vec<IvTermOracle> {
+        self.oracle
+            .iter()
+            .find(|o| o.asset == asset)
+            .map(|o| o.iv_term_oracles.clone())
+            .unwrap_or_default()
+    }
+
     pub fn get_instrument_data(
         &self,
         instrument_pubkey: &Pubkey,
@@ -45,6 +79,12 @@ impl Exchange {
         return None;
     }
 
+    /// get the asset of the instrument
+    pub fn get_instrument_asset(&self, instrument_pubkey: &Pubkey) -> Option<Asset> {
+        self.get_instrument_data(instrument_pubkey)
+            .map(|(instrument_common, _, _)| instrument_common.asset)
+    }
+
     pub fn get_instrument_pubkey(&self, asset: Option<Asset>) -> Vec<Pubkey> {
         let mut instrument_pubkey = vec![];
 
@@ -107,7 +147,7 @@ impl Exchange {
 }
 
 /// only keep the key data for a created Instrument
-#[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
 pub struct OracleData {
     pub asset: Asset,
 
@@ -115,6 +155,8 @@ pub struct OracleData {
     pub spot_oracle: Option<Pubkey>,
     /// trusted oracle account for iv
     pub iv_oracle: Option<Pubkey>,
+    /// trusted oracle accounts for iv term structure, sorted by tenor
+    pub iv_term_oracles: Vec<IvTermOracle>,
     // pub spot_price: u64,
 
     // pub iv: u64,
@@ -122,6 +164,24 @@ pub struct OracleData {
     // pub latest_update_timestamp: u64,
 }
 
+/// an iv oracle for a specific tenor
+#[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct IvTermOracle {
+    /// tenor of the iv feed in seconds, e.g. 7 days
+    pub tenor: u64,
+    /// trusted oracle account for the iv of this tenor
+    pub oracle: Pubkey,
+}
+
+/// spot correlation between two assets
+#[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct AssetCorrelation {
+    pub asset_a: Asset,
+    pub asset_b: Asset,
+    /// correlation between -1 and 1 (f_to_i_repr)
+    pub correlation: i64,
+}
+
 /// keep the common data for an instrument group
 #[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize, PartialEq)]
 pub struct InstrumentCommon {
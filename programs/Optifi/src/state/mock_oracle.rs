@@ -0,0 +1,20 @@
+use anchor_lang::prelude::*;
+use solana_program::pubkey::Pubkey;
+
+/// A local oracle feed which can stand in for a switchboard aggregator account,
+/// so that the oracle dependent instructions can be driven offline in tests.
+///
+/// The feed is only recognized by the oracle functions when the program is built
+/// with the `testing` feature, a mainnet/devnet build will never read it.
+#[account]
+#[derive(Default)]
+pub struct MockOracleAccount {
+    /// the authority which is allowed to update the feed
+    pub authority: Pubkey, // 32 bytes
+    /// spot price of the asset in usd (f_to_u_repr)
+    pub spot_price: u64, // 8 bytes
+    /// implied volatility of the asset, 0.8 means 80% (f_to_u_repr)
+    pub iv: u64, // 8 bytes
+    /// unix timestamp when the feed was updated
+    pub timestamp: u64, // 8 bytes
+}
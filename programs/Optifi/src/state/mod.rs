@@ -2,13 +2,21 @@ pub mod amm_state;
 pub mod exchange;
 pub mod liquidation_state;
 pub mod market_maker_account;
+pub mod mock_oracle;
+pub mod order_metadata;
 pub mod position;
+pub mod referrer_account;
+pub mod risk_parameter;
 pub mod user_account;
 
 pub use amm_state::*;
 pub use exchange::*;
 pub use liquidation_state::*;
+pub use mock_oracle::*;
+pub use order_metadata::*;
 pub use position::*;
+pub use referrer_account::*;
+pub use risk_parameter::*;
 pub use user_account::*;
 
 use anchor_lang::{prelude::*, AnchorDeserialize, AnchorSerialize};
@@ -32,7 +40,9 @@ pub struct OptifiMarket {
     pub bump: u8,
 }
 
-use crate::financial::Asset;
+use crate::constants::{MAX_MARGIN_STRESS_AGE, SECS_IN_STANDARD_YEAR};
+use crate::errors::ErrorCode;
+use crate::financial::{is_oracle_stale, Asset};
 
 #[account]
 #[derive(Default)]
@@ -44,12 +54,23 @@ pub struct MarginStressAccount {
     /// underlying asset
     pub asset: Asset, // 1 bytes
 
+    /// spot price of the asset in usdc
     pub spot_price: u64,
+    /// usdc/usd spot price cached when the margin stress is synced
+    pub usdc_spot_price: u64,
     pub iv: u64,
 
+    /// when the oracles were last synced
     pub timestamp: u64,
+    /// when the stress results were last calculated
+    pub calculated_timestamp: u64,
+    /// oldest update time of the oracles read at the last sync
+    pub oracle_timestamp: u64,
 
-    /// MarginStress's state indicator
+    /// the exchange's instruments epoch of the asset when the instrument list was last built
+    pub instruments_epoch: u64,
+
+    /// MarginStress's state indicator, the state of the calculation of the pending results
     pub state: MarginStressState,
     /// each instrument's state flag under the current MarginStress state
     pub flags: Vec<bool>,
@@ -59,10 +80,34 @@ pub struct MarginStressAccount {
     pub strikes: Vec<u64>,
     pub is_call: Vec<u8>,
     pub expiry_date: Vec<u64>,
+    /// each instrument's iv interpolated from the iv term structure to its expiry date
+    pub expiry_iv: Vec<u64>,
+
+    /// relative spot shock of each scenario of the stress grid
+    pub scenario_spot_shocks: Vec<i64>,
+    /// relative iv shock of each scenario of the stress grid
+    pub scenario_vol_shocks: Vec<i64>,
 
     pub option_price: Vec<u64>,
     pub intrinsic_value: Vec<u64>,
+    /// each instrument's price change under each scenario of the stress grid,
+    /// fully repriced at the shocked spot and iv
     pub option_price_delta_in_stress_price: Vec<Vec<i64>>,
+
+    /// the pending stress results, which are calculated from the oracle data of a sync
+    /// while the results above stay available, and replace them once they are complete
+    pub pending_spot_price: u64,
+    pub pending_iv: u64,
+    /// when the oracles of the pending results were synced
+    pub pending_timestamp: u64,
+    /// oldest update time of the oracles of the pending results
+    pub pending_oracle_timestamp: u64,
+    pub pending_expiry_iv: Vec<u64>,
+    pub pending_scenario_spot_shocks: Vec<i64>,
+    pub pending_scenario_vol_shocks: Vec<i64>,
+    pub pending_option_price: Vec<u64>,
+    pub pending_intrinsic_value: Vec<u64>,
+    pub pending_option_price_delta_in_stress_price: Vec<Vec<i64>>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, AnchorDeserialize, AnchorSerialize)]
@@ -87,6 +132,131 @@ impl MarginStressAccount {
             MarginStressState::Available => self.state = MarginStressState::Sync,
         }
     }
+    /// account space needed for `instruments` instruments and `scenarios` stress scenarios,
+    /// with the pending results
+    pub fn space(instruments: usize, scenarios: usize) -> usize {
+        // 8 discriminator + 32 + 1 + 1 + 8*6 + 8 epoch + 1 state + 4*11 vec prefixes
+        // + pending 8*4 + 4*6 vec prefixes
+        199 + instruments * (106 + 16 * scenarios) + scenarios * 32
+    }
+
+    /// rebuild the instrument list of the asset from the exchange and reset the stress results,
+    /// the account goes back to Sync
+    pub fn rebuild_instruments(&mut self, optifi_exchange: &Exchange) {
+        let (instruments, strikes, is_call, expiry_date) =
+            optifi_exchange.get_instrument_data_with_asset(self.asset);
+        let len = instruments.len();
+
+        self.instruments = instruments;
+        self.strikes = strikes;
+        self.is_call = is_call;
+        self.expiry_date = expiry_date;
+        self.instruments_epoch = optifi_exchange.instruments_epoch[self.asset as usize];
+
+        self.expiry_iv = vec![0; len];
+        self.flags = vec![false; len];
+        self.option_price = vec![0; len];
+        self.intrinsic_value = vec![0; len];
+        self.option_price_delta_in_stress_price = vec![vec![]; len];
+        self.calculated_timestamp = 0;
+        self.pending_expiry_iv = vec![0; len];
+        self.pending_option_price = vec![0; len];
+        self.pending_intrinsic_value = vec![0; len];
+        self.pending_option_price_delta_in_stress_price = vec![vec![]; len];
+        self.state = MarginStressState::Sync;
+    }
+
+    /// whether stress results have been calculated for the current instrument list,
+    /// they are dropped when the instruments are rebuilt or usdc is depegged
+    pub fn has_results(&self) -> bool {
+        self.calculated_timestamp != 0
+    }
+
+    /// start calculating the pending results from the synced oracle data,
+    /// the current results stay available meanwhile
+    pub fn start_calculation(
+        &mut self,
+        spot_price: u64,
+        iv: u64,
+        expiry_iv: Vec<u64>,
+        scenario_spot_shocks: Vec<i64>,
+        scenario_vol_shocks: Vec<i64>,
+        now: u64,
+        oracle_timestamp: u64,
+    ) {
+        let len = self.instruments.len();
+        self.pending_spot_price = spot_price;
+        self.pending_iv = iv;
+        self.pending_expiry_iv = expiry_iv;
+        self.pending_scenario_spot_shocks = scenario_spot_shocks;
+        self.pending_scenario_vol_shocks = scenario_vol_shocks;
+        self.pending_timestamp = now;
+        self.pending_oracle_timestamp = oracle_timestamp;
+        self.pending_option_price = vec![0; len];
+        self.pending_intrinsic_value = vec![0; len];
+        self.pending_option_price_delta_in_stress_price = vec![vec![]; len];
+        self.flags = vec![false; len];
+        self.state = MarginStressState::Calculate;
+    }
+
+    /// replace the current results with the completed pending results
+    pub fn publish_pending_results(&mut self) {
+        std::mem::swap(&mut self.spot_price, &mut self.pending_spot_price);
+        std::mem::swap(&mut self.iv, &mut self.pending_iv);
+        std::mem::swap(&mut self.expiry_iv, &mut self.pending_expiry_iv);
+        std::mem::swap(
+            &mut self.scenario_spot_shocks,
+            &mut self.pending_scenario_spot_shocks,
+        );
+        std::mem::swap(
+            &mut self.scenario_vol_shocks,
+            &mut self.pending_scenario_vol_shocks,
+        );
+        std::mem::swap(&mut self.option_price, &mut self.pending_option_price);
+        std::mem::swap(&mut self.intrinsic_value, &mut self.pending_intrinsic_value);
+        std::mem::swap(
+            &mut self.option_price_delta_in_stress_price,
+            &mut self.pending_option_price_delta_in_stress_price,
+        );
+        self.timestamp = self.pending_timestamp;
+        self.oracle_timestamp = self.pending_oracle_timestamp;
+        self.calculated_timestamp = self.pending_timestamp;
+        for flag in self.flags.iter_mut() {
+            *flag = false;
+        }
+        self.state = MarginStressState::Available;
+    }
+
+    /// check that the stress results are available, built from the exchange's current
+    /// instrument list and not older than MAX_MARGIN_STRESS_AGE,
+    /// from oracle data not older than MAX_ORACLE_AGE.
+    /// The results stay available while the pending results are calculated
+    pub fn check_available(&self, now: u64, instruments_epoch: u64) -> ProgramResult {
+        if self.instruments_epoch != instruments_epoch {
+            return Err(ErrorCode::MarginStressOutOfSync.into());
+        }
+        if !self.has_results() {
+            return Err(ErrorCode::WrongState.into());
+        }
+        if now.saturating_sub(self.timestamp) > MAX_MARGIN_STRESS_AGE {
+            return Err(ErrorCode::TimeOut.into());
+        }
+        if is_oracle_stale(self.oracle_timestamp, now) {
+            return Err(ErrorCode::StaleOracle.into());
+        }
+        Ok(())
+    }
+
+    /// time to maturity of each instrument in years, from the time the stress results
+    /// were calculated, which the option prices are of
+    pub fn get_time_to_maturity(&self) -> Vec<f32> {
+        self.expiry_date
+            .iter()
+            .map(|d| {
+                d.saturating_sub(self.calculated_timestamp) as f32 / SECS_IN_STANDARD_YEAR as f32
+            })
+            .collect()
+    }
     #[inline]
     pub fn get_option_price(&self, instrument: Pubkey) -> u64 {
         for (index, i) in self.instruments.iter().enumerate() {
@@ -0,0 +1,27 @@
+use anchor_lang::prelude::*;
+
+/// Metadata of a user's order, keyed by the client order id
+/// and matched to the order on the orderbook by its serum order id
+#[account]
+#[derive(Default)]
+pub struct OrderMetadata {
+    /// the user account which placed the order
+    pub user_account: Pubkey, // 32 bytes
+    /// the optifi market the order was placed on
+    pub optifi_market: Pubkey, // 32 bytes
+    /// client order id of the order
+    pub client_order_id: u64, // 8 bytes
+    /// serum order id of the order, 0 if nothing of the order rested on the orderbook
+    pub order_id: u128, // 16 bytes
+    /// unix timestamp after which the order can be cancelled by anyone
+    pub expiry: u64, // 8 bytes
+    /// bump seed used to derive this order metadata address
+    pub bump: u8, // 1 bytes
+}
+
+impl OrderMetadata {
+    /// whether the order can be cancelled by anyone
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiry
+    }
+}
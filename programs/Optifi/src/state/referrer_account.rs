@@ -0,0 +1,22 @@
+use anchor_lang::prelude::*;
+
+/// A referrer which receives a share of the trading fees paid by the users it referred
+#[account]
+#[derive(Default)]
+pub struct ReferrerAccount {
+    /// optifi exchange which the referrer account belongs to
+    pub optifi_exchange: Pubkey, // 32 bytes
+    /// the owner of the referrer account, who can claim the rebates
+    pub owner: Pubkey, // 32 bytes
+    /// usdc token account which the rebates are paid into,
+    /// its authority is this referrer account(pda)
+    pub rebate_vault: Pubkey, // 32 bytes
+    /// number of users who linked the referrer
+    pub referred_users: u64, // 8 bytes
+    /// rebates paid into the rebate vault, in usdc (6 decimals repr)
+    pub total_rebates: u64, // 8 bytes
+    /// rebates claimed from the rebate vault, in usdc (6 decimals repr)
+    pub claimed_rebates: u64, // 8 bytes
+    /// bump seed used to derive this referrer account address
+    pub bump: u8, // 1 bytes
+}
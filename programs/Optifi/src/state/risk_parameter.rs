@@ -0,0 +1,26 @@
+use crate::financial::Asset;
+use anchor_lang::prelude::*;
+use solana_program::pubkey::Pubkey;
+
+/// A stress scenario of the margin calculation
+#[derive(Default, Clone, Copy, Debug, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct StressScenario {
+    /// relative spot shock, -0.5 means the spot price drops by 50% (f_to_i_repr)
+    pub spot_shock: i64, // 8 bytes
+    /// relative iv shock, 0.3 means the iv rises by 30% (f_to_i_repr)
+    pub vol_shock: i64, // 8 bytes
+}
+
+/// The risk parameters of an asset, which are set by the exchange authority
+#[account]
+#[derive(Default)]
+pub struct RiskParameterAccount {
+    /// optifi exchange which the risk parameters belong to
+    pub optifi_exchange: Pubkey, // 32 bytes
+    /// bump seed used to derive this account address
+    pub bump: u8, // 1 bytes
+    /// underlying asset
+    pub asset: Asset, // 1 bytes
+    /// the stress scenarios the options are repriced against in margin_stress_calculate
+    pub scenarios: Vec<StressScenario>, // 4 + MAX_STRESS_SCENARIOS * 16 bytes
+}
@@ -4,7 +4,9 @@ use anchor_spl::token::accessor::amount;
 use solana_program::{program_error::ProgramError, program_pack::IsInitialized, pubkey::Pubkey};
 use std::{cmp::min, fmt::Debug};
 
-use crate::financial::Asset;
+use crate::constants::{FEE_TIERS, INITIAL_MARGIN_MULTIPLIER, MAX_LINKED_OWNERS, USDC_DECIMALS};
+use crate::financial::{Asset, MarginFunctionResult, SelfTradeMode};
+use crate::utils::get_serum_open_orders_pda;
 
 #[account]
 pub struct UserAccount {
@@ -34,8 +36,51 @@ pub struct UserAccount {
     /// the bump seed to get the address of this user account
     pub bump: u8,
 
-    /// maintanance margin
+    /// maintanance margin of each asset, the user is liquidated below it
     pub amount_to_reserve: [u64; 10],
+
+    /// initial margin of each asset, required to place new orders and withdraw
+    pub initial_margin: [u64; 10],
+
+    /// whether the user's books in different assets are margined together
+    pub cross_margin: bool,
+
+    /// maintanance margin credit for offsetting books in correlated assets,
+    /// only given in cross margin mode
+    pub cross_margin_credit: u64,
+
+    /// breakdown of the latest margin calculation of each asset
+    pub margin_reports: [MarginFunctionResult; 10],
+
+    /// market value of the positions of each asset, marked to the option prices
+    /// of the margin stress account, in usdc (6 decimals repr)
+    pub position_values: [i64; 10],
+
+    /// equity / maintanance margin, 6 decimals repr, see `get_equity`,
+    /// the user can be liquidated below 1, u64::MAX without any margin requirement
+    pub health_factor: u64,
+
+    /// maintanance margin / equity, 6 decimals repr,
+    /// u64::MAX when the user has no equity left
+    pub margin_ratio: u64,
+
+    /// notional traded on the orderbook, in usdc (6 decimals repr), which decides the fee tier
+    pub trading_volume: u64,
+
+    /// trading fees accrued since they were last collected into the protocol fee vault,
+    /// in usdc (6 decimals repr)
+    pub unpaid_fees: u64,
+
+    /// referrer account linked when the user account was initialized,
+    /// the default pubkey if the user wasn't referred
+    pub referrer: Pubkey,
+
+    /// what happens when the user's orders would match each other
+    pub self_trade_mode: SelfTradeMode,
+
+    /// open orders owners counted as the same owner as the user, e.g. the amm authority
+    /// or the user account of a linked market maker, the default pubkey for empty slots
+    pub linked_owners: [Pubkey; MAX_LINKED_OWNERS],
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, AnchorSerialize, AnchorDeserialize)]
@@ -43,9 +88,61 @@ pub struct UserPosition {
     instrument: Pubkey,
     long_qty: u64,
     short_qty: u64,
+    /// quantity of the resting bids and unsettled fills on the orderbook,
+    /// which may become long position at any time
+    open_bid_qty: u64,
+    /// net quantity traded on the orderbook, which the average entry price applies to
+    basis_qty: i64,
+    /// volume weighted average entry price, in usdc (6 decimals repr)
+    avg_entry_price: u64,
+    /// pnl realized by closing the position, in usdc (6 decimals repr)
+    realized_pnl: i64,
+    /// pnl of the open position marked to the option price of the margin stress account,
+    /// in usdc (6 decimals repr)
+    unrealized_pnl: i64,
+    /// native balances of the serum open orders account after the last operation on it,
+    /// the maker fills since are the difference to them
+    open_orders_balances: OpenOrdersBalances,
+}
+
+/// native coin and pc balances of a serum open orders account,
+/// the totals are the free amounts plus the amounts locked in the resting orders
+#[derive(Clone, Copy, Debug, Default, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct OpenOrdersBalances {
+    pub coin_free: u64,
+    pub coin_total: u64,
+    pub pc_free: u64,
+    pub pc_total: u64,
+}
+
+impl OpenOrdersBalances {
+    /// the coin locked in the resting asks
+    pub fn coin_locked(&self) -> u64 {
+        self.coin_total.saturating_sub(self.coin_free)
+    }
+
+    /// the pc locked in the resting bids
+    pub fn pc_locked(&self) -> u64 {
+        self.pc_total.saturating_sub(self.pc_free)
+    }
 }
 
 impl UserPosition {
+    /// a position without any quantity or trade in the instrument
+    pub fn new(instrument: Pubkey) -> Self {
+        UserPosition {
+            instrument,
+            long_qty: 0,
+            short_qty: 0,
+            open_bid_qty: 0,
+            basis_qty: 0,
+            avg_entry_price: 0,
+            realized_pnl: 0,
+            unrealized_pnl: 0,
+            open_orders_balances: OpenOrdersBalances::default(),
+        }
+    }
+
     /// get the instrument pubkey
     pub fn get_instrument(&self) -> &Pubkey {
         return &self.instrument;
@@ -56,6 +153,84 @@ impl UserPosition {
         return self.long_qty as i64 - self.short_qty as i64;
     }
 
+    /// get the quantity of the resting bids
+    pub fn get_open_bid_quantity(&self) -> u64 {
+        self.open_bid_qty
+    }
+
+    /// get the volume weighted average entry price
+    pub fn get_avg_entry_price(&self) -> u64 {
+        self.avg_entry_price
+    }
+
+    /// get the realized pnl
+    pub fn get_realized_pnl(&self) -> i64 {
+        self.realized_pnl
+    }
+
+    /// get the unrealized pnl at the last mark
+    pub fn get_unrealized_pnl(&self) -> i64 {
+        self.unrealized_pnl
+    }
+
+    /// apply a trade of `qty` contracts, positive for buying, for `cost` usdc,
+    /// negative for selling, to the average entry price and the realized pnl
+    ///
+    /// # Examples
+    /// ```rust
+    /// use optifi::state::UserPosition;
+    /// use solana_program::pubkey::Pubkey;
+    ///
+    /// let mut position = UserPosition::new(Pubkey::default());
+    /// // buy 10 at 2 then 10 at 4
+    /// position.apply_trade(10, 20);
+    /// position.apply_trade(10, 40);
+    /// assert_eq!(position.get_avg_entry_price(), 3);
+    ///
+    /// // sell 15 at 5, which closes 15 of the 20 bought at 3
+    /// position.apply_trade(-15, -75);
+    /// assert_eq!(position.get_realized_pnl(), 30);
+    /// assert_eq!(position.get_avg_entry_price(), 3);
+    ///
+    /// // sell 10 at 1, which closes the other 5 and opens a short of 5 at 1
+    /// position.apply_trade(-10, -10);
+    /// assert_eq!(position.get_realized_pnl(), 20);
+    /// assert_eq!(position.get_avg_entry_price(), 1);
+    ///
+    /// // a rebate without a fill is realized
+    /// position.apply_trade(0, -2);
+    /// assert_eq!(position.get_realized_pnl(), 22);
+    /// ```
+    pub fn apply_trade(&mut self, qty: i64, cost: i64) {
+        if qty == 0 {
+            // fees and rebates without a fill
+            self.realized_pnl -= cost;
+            return;
+        }
+        let price = ((cost as i128).abs() / (qty as i128).abs()) as i64;
+        let position = self.basis_qty;
+        if position == 0 || position.signum() == qty.signum() {
+            self.avg_entry_price = ((self.avg_entry_price as i128 * position.abs() as i128
+                + cost.abs() as i128)
+                / (position.abs() + qty.abs()) as i128) as u64;
+        } else {
+            let closed_qty = position.abs().min(qty.abs());
+            self.realized_pnl +=
+                closed_qty * (price - self.avg_entry_price as i64) * position.signum();
+            if qty.abs() > position.abs() {
+                self.avg_entry_price = price as u64;
+            } else if qty.abs() == position.abs() {
+                self.avg_entry_price = 0;
+            }
+        }
+        self.basis_qty = position + qty;
+    }
+
+    /// mark the open position to the option price
+    fn mark_to_market(&mut self, option_price: u64) {
+        self.unrealized_pnl = self.basis_qty * (option_price as i64 - self.avg_entry_price as i64);
+    }
+
     /// check if the instrument is still valid on the Optifi exchange
     pub fn is_valid(&self, instruments: &Vec<Pubkey>) -> bool {
         instruments.contains(&self.instrument)
@@ -91,9 +266,8 @@ impl UserAccount {
             p.short_qty += qty;
         } else {
             self.positions.push(UserPosition {
-                instrument,
-                long_qty: 0,
                 short_qty: qty,
+                ..UserPosition::new(instrument)
             });
         }
     }
@@ -108,9 +282,8 @@ impl UserAccount {
             p.long_qty += qty;
         } else {
             self.positions.push(UserPosition {
-                instrument,
                 long_qty: qty,
-                short_qty: 0,
+                ..UserPosition::new(instrument)
             });
         }
     }
@@ -125,9 +298,8 @@ impl UserAccount {
             p.long_qty = qty;
         } else {
             self.positions.push(UserPosition {
-                instrument,
                 long_qty: qty,
-                short_qty: 0,
+                ..UserPosition::new(instrument)
             });
         }
     }
@@ -142,13 +314,177 @@ impl UserAccount {
             p.short_qty = qty;
         } else {
             self.positions.push(UserPosition {
-                instrument,
-                long_qty: 0,
                 short_qty: qty,
+                ..UserPosition::new(instrument)
             });
         }
     }
 
+    /// update the quantity of the resting bids of the instrument
+    pub fn update_open_bid_quantity(&mut self, instrument: Pubkey, qty: u64) {
+        if let Some(p) = self
+            .positions
+            .iter_mut()
+            .find(|position| position.instrument == instrument)
+        {
+            p.open_bid_qty = qty;
+        } else if qty > 0 {
+            self.positions.push(UserPosition {
+                open_bid_qty: qty,
+                ..UserPosition::new(instrument)
+            });
+        }
+    }
+
+    /// apply a hypothetical change of the net quantity of the instrument, the opposite side
+    /// is reduced first, as the order settlement burns the long and short tokens
+    pub fn apply_position_delta(&mut self, instrument: Pubkey, quantity: i64) {
+        let position = self.get_or_add_position(instrument);
+        let qty = quantity.unsigned_abs();
+        if quantity > 0 {
+            let burnt = qty.min(position.short_qty);
+            position.short_qty -= burnt;
+            position.long_qty += qty - burnt;
+        } else {
+            let burnt = qty.min(position.long_qty);
+            position.long_qty -= burnt;
+            position.short_qty += qty - burnt;
+        }
+    }
+
+    /// get the position of the instrument, which is added if the user has none
+    fn get_or_add_position(&mut self, instrument: Pubkey) -> &mut UserPosition {
+        let index = match self
+            .positions
+            .iter()
+            .position(|position| position.instrument == instrument)
+        {
+            Some(index) => index,
+            None => {
+                self.positions.push(UserPosition::new(instrument));
+                self.positions.len() - 1
+            }
+        };
+        &mut self.positions[index]
+    }
+
+    /// record the maker fills since the last operation on the serum open orders account,
+    /// from its balances before the next operation, and keep its balances after that operation.
+    ///
+    /// Between two operations only the maker fills change the balances, a filled bid adds
+    /// the coin bought to the free coin and pays from the locked pc, a filled ask pays the coin
+    /// from the locked coin and adds the proceeds to the free pc. The bids and asks are applied
+    /// and charged the maker fee on their own, as a bid and an ask filled between two operations
+    /// don't net out in the fees.
+    ///
+    /// The liquidation instructions don't record their fills, as their order code is hidden.
+    pub fn record_open_orders_fills(
+        &mut self,
+        instrument: Pubkey,
+        balances_before_operation: OpenOrdersBalances,
+        balances_after_operation: OpenOrdersBalances,
+    ) {
+        let position = self.get_or_add_position(instrument);
+        let last = position.open_orders_balances;
+        let before = balances_before_operation;
+        let bought_qty = before.coin_free.saturating_sub(last.coin_free);
+        let bought_cost = last.pc_locked().saturating_sub(before.pc_locked());
+        let sold_qty = last.coin_locked().saturating_sub(before.coin_locked());
+        // the maker rebates are added to the free pc too, and count as proceeds
+        let sold_proceeds = before.pc_free.saturating_sub(last.pc_free);
+        if bought_qty != 0 || bought_cost != 0 {
+            position.apply_trade(bought_qty as i64, bought_cost as i64);
+        }
+        if sold_qty != 0 || sold_proceeds != 0 {
+            position.apply_trade(-(sold_qty as i64), -(sold_proceeds as i64));
+        }
+        position.open_orders_balances = balances_after_operation;
+
+        let mut maker_notional = 0;
+        if bought_qty != 0 {
+            maker_notional += bought_cost;
+        }
+        if sold_qty != 0 {
+            maker_notional += sold_proceeds;
+        }
+        let (maker_fee_bps, _) = self.get_fee_rates();
+        self.accrue_fee(maker_notional, maker_fee_bps);
+    }
+
+    /// record what a new order filled as taker, `qty` contracts, positive for buying,
+    /// for `cost` usdc, negative for selling, and accrue the taker fee on it
+    pub fn record_taker_fill(&mut self, instrument: Pubkey, qty: i64, cost: i64) {
+        if qty == 0 {
+            return;
+        }
+        self.get_or_add_position(instrument).apply_trade(qty, cost);
+        let (_, taker_fee_bps) = self.get_fee_rates();
+        self.accrue_fee(cost.unsigned_abs(), taker_fee_bps);
+    }
+
+    /// get the serum open orders accounts of the linked owners on the serum market
+    pub fn get_linked_open_orders(
+        &self,
+        serum_market: &Pubkey,
+        program_id: &Pubkey,
+    ) -> Vec<Pubkey> {
+        self.linked_owners
+            .iter()
+            .filter(|owner| **owner != Pubkey::default())
+            .map(|owner| {
+                get_serum_open_orders_pda(&self.optifi_exchange, serum_market, owner, program_id).0
+            })
+            .collect()
+    }
+
+    /// get the maker and taker fees in bps of the user's volume tier
+    pub fn get_fee_rates(&self) -> (u64, u64) {
+        let volume = self.trading_volume / 10u64.pow(USDC_DECIMALS);
+        FEE_TIERS
+            .iter()
+            .rev()
+            .find(|(min_volume, _, _)| volume >= *min_volume)
+            .map_or((FEE_TIERS[0].1, FEE_TIERS[0].2), |&(_, maker, taker)| {
+                (maker, taker)
+            })
+    }
+
+    /// accrue the fee of `fee_bps` on the traded notional, rounded up
+    fn accrue_fee(&mut self, notional: u64, fee_bps: u64) {
+        if notional == 0 {
+            return;
+        }
+        let fee = (notional as u128 * fee_bps as u128 + 9_999) / 10_000;
+        self.unpaid_fees += fee as u64;
+        self.trading_volume += notional;
+    }
+
+    /// mark the positions in the given instruments to their option prices,
+    /// returns the total unrealized pnl of them
+    pub fn mark_to_market(&mut self, instruments: &Vec<Pubkey>, option_price: &Vec<u64>) -> i64 {
+        let mut unrealized_pnl = 0;
+        for position in self.positions.iter_mut() {
+            if let Some(index) = instruments.iter().position(|i| i == &position.instrument) {
+                position.mark_to_market(option_price[index]);
+                unrealized_pnl += position.unrealized_pnl;
+            }
+        }
+        unrealized_pnl
+    }
+
+    /// get the quantities of the resting bids of the given instruments
+    pub fn get_open_bid_quantities(&self, instruments: &Vec<Pubkey>) -> Vec<i64> {
+        instruments
+            .iter()
+            .map(|instrument| {
+                self.positions
+                    .iter()
+                    .find(|position| &position.instrument == instrument)
+                    .map_or(0, |position| position.open_bid_qty as i64)
+            })
+            .collect()
+    }
+
     /// get the net quantity
     pub fn get_quantity(&self, instrument: Pubkey) -> i64 {
         if let Some(p) = self
@@ -162,9 +498,65 @@ impl UserAccount {
         }
     }
 
-    /// get the total margin reserve
+    /// keep the margin breakdown of the asset, and set the margin requirements from it
+    pub fn set_margin_report(&mut self, asset: Asset, margin_result: MarginFunctionResult) {
+        self.margin_reports[asset as usize] = margin_result;
+        self.set_margin_requirement(asset, -margin_result.total_margin.min(0) as u64);
+    }
+
+    /// get the net quantities of the given instruments, 0 if the user has no position
+    pub fn get_positions(&self, instruments: &Vec<Pubkey>) -> Vec<i64> {
+        instruments
+            .iter()
+            .map(|instrument| self.get_quantity(*instrument))
+            .collect()
+    }
+
+    /// get the total margin reserve, less the cross margin credit
     pub fn get_maintanance_margin(&self) -> u64 {
-        self.amount_to_reserve.iter().sum()
+        self.amount_to_reserve
+            .iter()
+            .sum::<u64>()
+            .saturating_sub(self.cross_margin_credit)
+    }
+
+    /// get the total initial margin, less the cross margin credit
+    pub fn get_initial_margin(&self) -> u64 {
+        let credit = (self.cross_margin_credit as f64 * INITIAL_MARGIN_MULTIPLIER as f64) as u64;
+        self.initial_margin.iter().sum::<u64>().saturating_sub(credit)
+    }
+
+    /// get the equity which is held against the maintanance margin, for the health
+    /// and the liquidation: the collateral less the unsettled losses. The positions aren't
+    /// added, the margin requirements already count their market value as net premium
+    pub fn get_equity(&self, user_margin_account: &AccountInfo) -> u64 {
+        self.get_available_margin(user_margin_account)
+    }
+
+    /// update the health factor and the margin ratio from the equity
+    /// and the maintanance margin of the latest margin calculation
+    pub fn update_health(&mut self, user_margin_account: &AccountInfo) {
+        let equity = self.get_equity(user_margin_account) as u128;
+        let maintanance_margin = self.get_maintanance_margin() as u128;
+        let unit = 10u128.pow(USDC_DECIMALS);
+
+        self.health_factor = if maintanance_margin == 0 {
+            u64::MAX
+        } else {
+            (equity * unit / maintanance_margin).min(u64::MAX as u128) as u64
+        };
+        self.margin_ratio = if equity == 0 {
+            u64::MAX
+        } else {
+            (maintanance_margin * unit / equity).min(u64::MAX as u128) as u64
+        };
+    }
+
+    /// set the maintanance margin of the asset, and the initial margin derived from it
+    pub fn set_margin_requirement(&mut self, asset: Asset, maintanance_margin: u64) {
+        self.amount_to_reserve[asset as usize] = maintanance_margin;
+        self.initial_margin[asset as usize] =
+            (maintanance_margin as f64 * INITIAL_MARGIN_MULTIPLIER as f64) as u64;
     }
 }
 
@@ -50,6 +50,16 @@ pub const PREFIX_MM_LIQUIDITY_AUTH: &str = "mm_liquidity_auth";
 /// used to derive margin stress account address
 pub const PREFIX_MARGIN_STRESS: &str = "margin_stress";
 
+/// used to derive the risk parameter account address of an asset
+pub const PREFIX_RISK_PARAMETER: &str = "risk_parameter";
+
+/// used to derive the address of the metadata of a user's order
+pub const PREFIX_ORDER_METADATA: &str = "order_metadata";
+
+/// 1. used to derive the address of a referrer account
+/// 2. this pda has the authority over the referrer's rebate vault
+pub const PREFIX_REFERRER_ACCOUNT: &str = "referrer_account";
+
 /// get the user account pda and its bump seed
 pub fn get_user_account_pda(
     optifi_exchange: &Pubkey,
@@ -149,3 +159,21 @@ pub fn get_amm_liquidity_auth_pda(optifi_exchange: &Pubkey, program_id: &Pubkey)
         program_id,
     )
 }
+
+/// get the serum open orders pda of an owner, a user account or the amm authority, on a serum market
+pub fn get_serum_open_orders_pda(
+    optifi_exchange: &Pubkey,
+    serum_market: &Pubkey,
+    owner: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PREFIX_SERUM_OPEN_ORDERS.as_bytes(),
+            optifi_exchange.as_ref(),
+            serum_market.as_ref(),
+            owner.as_ref(),
+        ],
+        program_id,
+    )
+}
@@ -0,0 +1,712 @@
+//! Offline tests of the mock oracle feeds, which stand in for the switchboard feeds
+//! so that the oracle dependent instructions can be driven by solana-program-test.
+//!
+//! Run them with `cargo test --features testing`.
+#![cfg(feature = "testing")]
+
+use anchor_lang::prelude::ProgramError;
+use anchor_lang::{AccountDeserialize, AccountSerialize, InstructionData, ToAccountMetas};
+use optifi::constants::MAX_ORACLE_AGE;
+use optifi::errors::ErrorCode;
+use optifi::financial::instruments::{ExpiryType, InstrumentType};
+use optifi::financial::{
+    get_asset_to_usd_spot, get_iv, get_oracle_timestamp, is_oracle_stale, Chain, Duration,
+};
+use optifi::instructions::{
+    chain_data_to_seed_string, ChainData, InitUserAccountBumpSeeds, InitializeExchangeData,
+    MockOracleData,
+};
+use optifi::state::{
+    Exchange, MarginStressAccount, MarginStressState, MockOracleAccount, OptifiMarket,
+};
+use optifi::utils::{
+    PREFIX_INSTRUMENT, PREFIX_LIQUIDATION_STATE, PREFIX_MARGIN_STRESS, PREFIX_OPTIFI_EXCHANGE,
+    PREFIX_RISK_PARAMETER, PREFIX_USER_ACCOUNT,
+};
+use solana_program_test::{processor, BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    account_info::AccountInfo,
+    clock::Clock,
+    hash::Hash,
+    instruction::{Instruction, InstructionError},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
+
+const UUID: &str = "mocktx";
+const BTC: u8 = 0;
+const USDC_DECIMALS: u8 = 6;
+
+async fn process(
+    banks_client: &mut BanksClient,
+    recent_blockhash: Hash,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    transaction.sign(signers, recent_blockhash);
+    banks_client.process_transaction(transaction).await
+}
+
+/// check that the transaction failed with the error of the program
+fn assert_program_error(result: Result<(), TransportError>, error: ErrorCode) {
+    let code = match ProgramError::from(error) {
+        ProgramError::Custom(code) => code,
+        other => panic!("not a custom error: {:?}", other),
+    };
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(failed),
+        ))) => assert_eq!(failed, code),
+        other => panic!("expected the custom error {}, got {:?}", code, other),
+    }
+}
+
+/// an account of the program, with the anchor discriminator
+fn program_account<T: AccountSerialize>(state: &T) -> Account {
+    let mut data = vec![];
+    state.try_serialize(&mut data).unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: optifi::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// an account of the token program
+fn token_program_account<T: Pack>(state: T) -> Account {
+    let mut data = vec![0; T::LEN];
+    T::pack(state, &mut data).unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: spl_token::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn mint_account() -> Account {
+    token_program_account(spl_token::state::Mint {
+        mint_authority: COption::None,
+        supply: 0,
+        decimals: USDC_DECIMALS,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    })
+}
+
+fn token_account(mint: &Pubkey, owner: &Pubkey) -> Account {
+    token_program_account(spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount: 0,
+        delegate: COption::None,
+        state: spl_token::state::AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    })
+}
+
+fn init_mock_oracle(mock_oracle: &Pubkey, authority: &Pubkey, data: MockOracleData) -> Instruction {
+    Instruction {
+        program_id: optifi::ID,
+        accounts: optifi::accounts::InitializeMockOracle {
+            mock_oracle: *mock_oracle,
+            authority: *authority,
+            payer: *authority,
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: optifi::instruction::InitMockOracle { data }.data(),
+    }
+}
+
+fn set_mock_oracle(mock_oracle: &Pubkey, authority: &Pubkey, data: MockOracleData) -> Instruction {
+    Instruction {
+        program_id: optifi::ID,
+        accounts: optifi::accounts::SetMockOracle {
+            mock_oracle: *mock_oracle,
+            authority: *authority,
+        }
+        .to_account_metas(None),
+        data: optifi::instruction::SetMockOracle { data }.data(),
+    }
+}
+
+/// an exchange whose btc and usdc oracles are mock feeds, with the btc margin stress
+/// synced and calculated from them
+struct MockExchange {
+    banks_client: BanksClient,
+    payer: Keypair,
+    recent_blockhash: Hash,
+    now: u64,
+    exchange: Pubkey,
+    margin_stress: Pubkey,
+    btc_spot: Pubkey,
+    btc_iv: Pubkey,
+    usdc_spot: Pubkey,
+}
+
+impl MockExchange {
+    async fn start(mut program_test: ProgramTest, usdc_mint: Pubkey) -> Self {
+        let fee_vault = Pubkey::new_unique();
+        program_test.add_account(usdc_mint, mint_account());
+        program_test.add_account(fee_vault, token_account(&usdc_mint, &Pubkey::new_unique()));
+        let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+        let now = banks_client
+            .get_sysvar::<Clock>()
+            .await
+            .unwrap()
+            .unix_timestamp as u64;
+
+        // the mock feeds, fresh at the start
+        let (btc_spot, btc_iv, usdc_spot) = (Keypair::new(), Keypair::new(), Keypair::new());
+        for (feed, spot_price, iv) in [
+            (&btc_spot, 40_000_000_000, 0),
+            (&btc_iv, 0, 600_000),
+            (&usdc_spot, 1_000_000, 0),
+        ] {
+            let init = init_mock_oracle(
+                &feed.pubkey(),
+                &payer.pubkey(),
+                MockOracleData {
+                    spot_price,
+                    iv,
+                    timestamp: now,
+                },
+            );
+            process(
+                &mut banks_client,
+                recent_blockhash,
+                &payer,
+                &[init],
+                &[&payer, feed],
+            )
+            .await
+            .unwrap();
+        }
+
+        let (exchange, exchange_bump) = Pubkey::find_program_address(
+            &[PREFIX_OPTIFI_EXCHANGE.as_bytes(), UUID.as_bytes()],
+            &optifi::ID,
+        );
+        let initialize = Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::InitializeOptiFiExchange {
+                optifi_exchange: exchange,
+                authority: payer.pubkey(),
+                usdc_central_pool: Pubkey::new_unique(),
+                fee_vault,
+                payer: payer.pubkey(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::Initialize {
+                bump: exchange_bump,
+                data: InitializeExchangeData {
+                    uuid: UUID.to_string(),
+                    version: 1,
+                    exchange_authority: payer.pubkey(),
+                    owner: payer.pubkey(),
+                    usdc_mint,
+                    btc_spot_oracle: btc_spot.pubkey(),
+                    eth_spot_oracle: Pubkey::new_unique(),
+                    usdc_spot_oracle: usdc_spot.pubkey(),
+                    btc_iv_oracle: btc_iv.pubkey(),
+                    eth_iv_oracle: Pubkey::new_unique(),
+                },
+            }
+            .data(),
+        };
+
+        let (risk_parameter, risk_parameter_bump) = Pubkey::find_program_address(
+            &[PREFIX_RISK_PARAMETER.as_bytes(), exchange.as_ref(), &[BTC]],
+            &optifi::ID,
+        );
+        let init_risk_parameter = Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::InitRiskParameterContext {
+                optifi_exchange: exchange,
+                risk_parameter_account: risk_parameter,
+                authority: payer.pubkey(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::InitRiskParameter {
+                bump: risk_parameter_bump,
+                asset: BTC,
+            }
+            .data(),
+        };
+
+        let (margin_stress, margin_stress_bump) = Pubkey::find_program_address(
+            &[PREFIX_MARGIN_STRESS.as_bytes(), exchange.as_ref(), &[BTC]],
+            &optifi::ID,
+        );
+        let margin_stress_init = Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::InitMarginStressContext {
+                optifi_exchange: exchange,
+                margin_stress_account: margin_stress,
+                payer: payer.pubkey(),
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::MarginStressInit {
+                bump: margin_stress_bump,
+                asset: BTC,
+            }
+            .data(),
+        };
+        process(
+            &mut banks_client,
+            recent_blockhash,
+            &payer,
+            &[initialize, init_risk_parameter, margin_stress_init],
+            &[&payer],
+        )
+        .await
+        .unwrap();
+
+        // the margin stress is synced from the mock feeds, and its results are published
+        // once calculated, there's no instrument listed yet
+        let margin_stress_sync = Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::SyncMarginStressContext {
+                optifi_exchange: exchange,
+                margin_stress_account: margin_stress,
+                risk_parameter_account: risk_parameter,
+                asset_feed: btc_spot.pubkey(),
+                usdc_feed: usdc_spot.pubkey(),
+                iv_feed: btc_iv.pubkey(),
+                clock: sysvar::clock::ID,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::MarginStressSync {}.data(),
+        };
+        let margin_stress_calculate = Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::CalculateMarginStressContext {
+                optifi_exchange: exchange,
+                margin_stress_account: margin_stress,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::MarginStressCalculate {}.data(),
+        };
+        process(
+            &mut banks_client,
+            recent_blockhash,
+            &payer,
+            &[margin_stress_sync, margin_stress_calculate],
+            &[&payer],
+        )
+        .await
+        .unwrap();
+
+        MockExchange {
+            banks_client,
+            payer,
+            recent_blockhash,
+            now,
+            exchange,
+            margin_stress,
+            btc_spot: btc_spot.pubkey(),
+            btc_iv: btc_iv.pubkey(),
+            usdc_spot: usdc_spot.pubkey(),
+        }
+    }
+
+    async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), TransportError> {
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+        process(
+            &mut self.banks_client,
+            self.recent_blockhash,
+            &self.payer,
+            instructions,
+            &all_signers,
+        )
+        .await
+    }
+
+    async fn set_feed(&mut self, feed: Pubkey, spot_price: u64, iv: u64, timestamp: u64) {
+        let set = set_mock_oracle(
+            &feed,
+            &self.payer.pubkey(),
+            MockOracleData {
+                spot_price,
+                iv,
+                timestamp,
+            },
+        );
+        self.process(&[set], &[]).await.unwrap();
+    }
+
+    async fn account<T: AccountDeserialize>(&mut self, key: Pubkey) -> T {
+        let account = self.banks_client.get_account(key).await.unwrap().unwrap();
+        T::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    fn create_new_instrument(&self, instrument_idx: u8) -> Instruction {
+        let data = ChainData {
+            asset: BTC,
+            instrument_type: InstrumentType::Call as u8,
+            expiry_date: self.now + 7 * 24 * 3600,
+            duration: Duration::Weekly as u8,
+            start: self.now,
+            expiry_type: ExpiryType::Standard as u8,
+            authority: self.payer.pubkey(),
+            contract_size: 100,
+            instrument_idx,
+        };
+        let (instrument, bump) = Pubkey::find_program_address(
+            &[
+                PREFIX_INSTRUMENT.as_bytes(),
+                self.exchange.as_ref(),
+                chain_data_to_seed_string(&data).as_bytes(),
+            ],
+            &optifi::ID,
+        );
+        Instruction {
+            program_id: optifi::ID,
+            accounts: optifi::accounts::CreateInstrument {
+                optifi_exchange: self.exchange,
+                instrument,
+                payer: self.payer.pubkey(),
+                system_program: system_program::ID,
+                asset_spot_price_oracle_feed: self.btc_spot,
+                asset_iv_oracle_feed: self.btc_iv,
+                margin_stress_account: self.margin_stress,
+                clock: sysvar::clock::ID,
+            }
+            .to_account_metas(None),
+            data: optifi::instruction::CreateNewInstrument { bump, data }.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn mock_oracle_feeds_the_oracle_functions() {
+    let program_test = ProgramTest::new("optifi", optifi::ID, processor!(optifi::entry));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let mock_oracle = Keypair::new();
+
+    let init = init_mock_oracle(
+        &mock_oracle.pubkey(),
+        &payer.pubkey(),
+        MockOracleData {
+            spot_price: 50_000_000_000,
+            iv: 800_000,
+            timestamp: 1_000,
+        },
+    );
+    process(
+        &mut banks_client,
+        recent_blockhash,
+        &payer,
+        &[init],
+        &[&payer, &mock_oracle],
+    )
+    .await
+    .unwrap();
+
+    let set = set_mock_oracle(
+        &mock_oracle.pubkey(),
+        &payer.pubkey(),
+        MockOracleData {
+            spot_price: 40_000_000_000,
+            iv: 600_000,
+            timestamp: 2_000,
+        },
+    );
+    process(
+        &mut banks_client,
+        recent_blockhash,
+        &payer,
+        &[set],
+        &[&payer],
+    )
+    .await
+    .unwrap();
+
+    let account: Account = banks_client
+        .get_account(mock_oracle.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let feed = MockOracleAccount::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(feed.authority, payer.pubkey());
+    assert_eq!(feed.spot_price, 40_000_000_000);
+    assert_eq!(feed.iv, 600_000);
+    assert_eq!(feed.timestamp, 2_000);
+
+    // the oracle functions read the feed as they read a switchboard aggregator
+    let key = mock_oracle.pubkey();
+    let mut lamports = account.lamports;
+    let mut data = account.data.clone();
+    let feed_account = AccountInfo::new(
+        &key,
+        false,
+        false,
+        &mut lamports,
+        &mut data,
+        &account.owner,
+        false,
+        0,
+    );
+    assert_eq!(get_asset_to_usd_spot(&feed_account), 40_000.0);
+    assert_eq!(get_iv(&feed_account), 0.6);
+    assert_eq!(get_oracle_timestamp(&feed_account), 2_000);
+
+    // the feed timestamp drives the staleness checks
+    let timestamp = get_oracle_timestamp(&feed_account);
+    assert!(!is_oracle_stale(timestamp, 2_000 + MAX_ORACLE_AGE));
+    assert!(is_oracle_stale(timestamp, 2_001 + MAX_ORACLE_AGE));
+}
+
+#[tokio::test]
+async fn mock_oracle_is_only_set_by_its_authority() {
+    let program_test = ProgramTest::new("optifi", optifi::ID, processor!(optifi::entry));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let mock_oracle = Keypair::new();
+    let other = Keypair::new();
+
+    let init = init_mock_oracle(
+        &mock_oracle.pubkey(),
+        &payer.pubkey(),
+        MockOracleData::default(),
+    );
+    process(
+        &mut banks_client,
+        recent_blockhash,
+        &payer,
+        &[init],
+        &[&payer, &mock_oracle],
+    )
+    .await
+    .unwrap();
+
+    let set = set_mock_oracle(
+        &mock_oracle.pubkey(),
+        &other.pubkey(),
+        MockOracleData {
+            spot_price: 1,
+            iv: 1,
+            timestamp: 1,
+        },
+    );
+    assert!(process(
+        &mut banks_client,
+        recent_blockhash,
+        &payer,
+        &[set],
+        &[&payer, &other]
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn mock_feeds_drive_the_margin_stress_and_instrument_creation() {
+    let program_test = ProgramTest::new("optifi", optifi::ID, processor!(optifi::entry));
+    let mut exchange = MockExchange::start(program_test, Pubkey::new_unique()).await;
+
+    // the margin stress is synced from the feeds, and published once calculated
+    let margin_stress: MarginStressAccount = exchange.account(exchange.margin_stress).await;
+    assert_eq!(margin_stress.usdc_spot_price, 1_000_000);
+    assert_eq!(margin_stress.spot_price, 40_000_000_000);
+    assert_eq!(margin_stress.iv, 600_000);
+    assert_eq!(margin_stress.oracle_timestamp, exchange.now);
+    assert!(margin_stress.state == MarginStressState::Available);
+    assert!(margin_stress.has_results());
+
+    // an instrument is created at the strikes of the fresh feeds
+    let create = exchange.create_new_instrument(0);
+    exchange.process(&[create], &[]).await.unwrap();
+    let optifi_exchange: Exchange = exchange.account(exchange.exchange).await;
+    assert_eq!(optifi_exchange.instrument_common.len(), 1);
+    assert_eq!(optifi_exchange.instruments_epoch[BTC as usize], 1);
+    let strike = optifi_exchange.instrument_unique[0][0].strike;
+    assert!(strike > 0);
+
+    // and isn't created once the spot feed is stale
+    let stale = exchange.now - MAX_ORACLE_AGE - 1;
+    exchange
+        .set_feed(exchange.btc_spot, 40_000_000_000, 0, stale)
+        .await;
+    let create = exchange.create_new_instrument(1);
+    assert_program_error(
+        exchange.process(&[create], &[]).await,
+        ErrorCode::StaleOracle,
+    );
+}
+
+#[tokio::test]
+async fn market_settlement_rejects_stale_and_depegged_feeds() {
+    let mut program_test = ProgramTest::new("optifi", optifi::ID, processor!(optifi::entry));
+
+    // an expired instrument listed on an optifi market, and a user with a margin account
+    let instrument = Pubkey::new_unique();
+    let optifi_market = Pubkey::new_unique();
+    let serum_market = Pubkey::new_unique();
+    let (long_mint, short_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    program_test.add_account(
+        instrument,
+        program_account(&Chain {
+            asset: BTC,
+            instrument_type: InstrumentType::Call,
+            strike: 40_000,
+            expiry_date: 1,
+            duration: Duration::Weekly,
+            start: 0,
+            expiry_type: ExpiryType::Standard,
+            authority: Pubkey::default(),
+            is_listed_on_market: true,
+            contract_size: 100,
+        }),
+    );
+    program_test.add_account(
+        optifi_market,
+        program_account(&OptifiMarket {
+            optifi_market_id: 1,
+            serum_market,
+            instrument,
+            instrument_long_spl_token: long_mint,
+            instrument_short_spl_token: short_mint,
+            ..OptifiMarket::default()
+        }),
+    );
+    program_test.add_account(long_mint, mint_account());
+    program_test.add_account(short_mint, mint_account());
+    let usdc_mint = Pubkey::new_unique();
+    let user = Keypair::new();
+    let user_margin_account = Pubkey::new_unique();
+    program_test.add_account(
+        user_margin_account,
+        token_account(&usdc_mint, &user.pubkey()),
+    );
+    program_test.add_account(
+        user.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    let mut exchange = MockExchange::start(program_test, usdc_mint).await;
+
+    let (user_account, user_account_bump) = Pubkey::find_program_address(
+        &[
+            PREFIX_USER_ACCOUNT.as_bytes(),
+            exchange.exchange.as_ref(),
+            user.pubkey().as_ref(),
+        ],
+        &optifi::ID,
+    );
+    let (liquidation_account, liquidation_account_bump) = Pubkey::find_program_address(
+        &[
+            PREFIX_LIQUIDATION_STATE.as_bytes(),
+            exchange.exchange.as_ref(),
+            user_account.as_ref(),
+        ],
+        &optifi::ID,
+    );
+    let init_user_account = Instruction {
+        program_id: optifi::ID,
+        accounts: optifi::accounts::InitializeUserAccount {
+            optifi_exchange: exchange.exchange,
+            user_account,
+            liquidation_account,
+            user_margin_account_usdc: user_margin_account,
+            owner: user.pubkey(),
+            payer: exchange.payer.pubkey(),
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            rent: sysvar::rent::ID,
+        }
+        .to_account_metas(None),
+        data: optifi::instruction::InitUserAccount {
+            bump: InitUserAccountBumpSeeds {
+                user_account: user_account_bump,
+                liquidation_account: liquidation_account_bump,
+            },
+        }
+        .data(),
+    };
+    exchange
+        .process(&[init_user_account], &[&user])
+        .await
+        .unwrap();
+
+    // the serum accounts aren't read before the oracles are checked
+    let record_pnl = Instruction {
+        program_id: optifi::ID,
+        accounts: optifi::accounts::RecordPnLForOneUser {
+            optifi_exchange: exchange.exchange,
+            user_account,
+            optifi_market,
+            serum_market,
+            user_serum_open_orders: Pubkey::new_unique(),
+            instrument,
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            event_queue: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            vault_signer: Pubkey::new_unique(),
+            user_margin_account,
+            instrument_long_spl_token_mint: long_mint,
+            instrument_short_spl_token_mint: short_mint,
+            user_instrument_long_token_vault: Pubkey::new_unique(),
+            user_instrument_short_token_vault: Pubkey::new_unique(),
+            prune_authority: Pubkey::new_unique(),
+            serum_dex_program_id: Pubkey::new_unique(),
+            token_program: spl_token::ID,
+            clock: sysvar::clock::ID,
+            asset_spot_price_oracle_feed: exchange.btc_spot,
+            usdc_spot_price_oracle_feed: exchange.usdc_spot,
+        }
+        .to_account_metas(None),
+        data: optifi::instruction::RecordPnlForOneUser {}.data(),
+    };
+
+    // the user isn't settled at a stale usdc price
+    let stale = exchange.now - MAX_ORACLE_AGE - 1;
+    exchange
+        .set_feed(exchange.usdc_spot, 1_000_000, 0, stale)
+        .await;
+    assert_program_error(
+        exchange.process(&[record_pnl.clone()], &[]).await,
+        ErrorCode::StaleOracle,
+    );
+
+    // nor while usdc is depegged, the transfer keeps the transaction distinct from the last one
+    exchange
+        .set_feed(exchange.usdc_spot, 900_000, 0, exchange.now)
+        .await;
+    let payer = exchange.payer.pubkey();
+    let transfer = system_instruction::transfer(&payer, &payer, 1);
+    assert_program_error(
+        exchange.process(&[transfer, record_pnl], &[]).await,
+        ErrorCode::UsdcDepegged,
+    );
+}